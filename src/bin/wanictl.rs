@@ -0,0 +1,155 @@
+use clap::{Parser, Subcommand};
+use std::{path::PathBuf, process::ExitCode};
+use wanipaper::ipc::{self, IpcError, PassSelector, Request, Response, Status};
+
+/// Control a running wanipaper
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path to the control socket
+    #[arg(short, long)]
+    socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Show the next image
+    Next {
+        /// Render pass index or name, all rotating passes if unset
+        #[arg(short, long)]
+        pass: Option<PassSelector>,
+    },
+    /// Show the previous image
+    Prev {
+        /// Render pass index or name, all rotating passes if unset
+        #[arg(short, long)]
+        pass: Option<PassSelector>,
+    },
+    /// Show an image until the render pass next rotates
+    Set {
+        /// Render pass index or name
+        pass: PassSelector,
+        /// Image ident
+        image: String,
+    },
+    /// Stop rotation timers
+    Pause {
+        /// Render pass index or name, all rotating passes if unset
+        #[arg(short, long)]
+        pass: Option<PassSelector>,
+    },
+    /// Restart rotation timers
+    Resume {
+        /// Render pass index or name, all rotating passes if unset
+        #[arg(short, long)]
+        pass: Option<PassSelector>,
+    },
+    /// Reload the config
+    Reload,
+    /// Report render passes and displays
+    Status {
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Could not reach the daemon
+const EXIT_CONNECTION: u8 = 3;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let Some(socket) = cli.socket.or_else(ipc::socket_path) else {
+        eprintln!("wanictl: $XDG_RUNTIME_DIR is not set, use --socket");
+        return ExitCode::from(EXIT_CONNECTION);
+    };
+
+    let mut json = false;
+    let request = match cli.command {
+        Command::Next { pass } => Request::Next { pass },
+        Command::Prev { pass } => Request::Prev { pass },
+        Command::Set { pass, image } => Request::Set { pass, image },
+        Command::Pause { pass } => Request::Pause { pass },
+        Command::Resume { pass } => Request::Resume { pass },
+        Command::Reload => Request::Reload,
+        Command::Status { json: j } => {
+            json = j;
+            Request::Status
+        }
+    };
+
+    let response = match ipc::request(&socket, &request) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("wanictl: failed to reach wanipaper at {socket:?}: {e}");
+            return ExitCode::from(EXIT_CONNECTION);
+        }
+    };
+
+    match response {
+        Response::Ok => ExitCode::SUCCESS,
+        Response::Status(status) => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status).unwrap());
+            } else {
+                print_status(&status);
+            }
+            ExitCode::SUCCESS
+        }
+        Response::Error { error } => {
+            eprintln!("wanictl: {error}");
+            ExitCode::from(exit_code(&error))
+        }
+    }
+}
+
+/// Exit code for each daemon error, 1 and 2 are left for generic and usage errors
+fn exit_code(error: &IpcError) -> u8 {
+    match error {
+        IpcError::InvalidRequest(_) => 4,
+        IpcError::UnknownPass(_) => 5,
+        IpcError::UnknownImage(_) => 6,
+        IpcError::NotRotating(_) => 7,
+        IpcError::Reload(_) => 8,
+    }
+}
+
+fn print_status(status: &Status) {
+    for pass in &status.passes {
+        let name = pass
+            .name
+            .as_ref()
+            .map(|n| format!(" '{n}'"))
+            .unwrap_or_default();
+
+        print!(
+            "pass {}{name} -> {}: {}",
+            pass.index, pass.target, pass.image
+        );
+        if pass.images.len() > 1 {
+            let position = pass.images.iter().position(|i| *i == pass.image);
+            match position {
+                Some(p) => print!(" [{}/{}]", p + 1, pass.images.len()),
+                None => print!(" [-/{}]", pass.images.len()),
+            }
+        }
+        if let Some(rotate) = pass.rotate {
+            print!(" every {rotate}s");
+        }
+        if pass.paused {
+            print!(" (paused)");
+        }
+        println!();
+    }
+
+    for display in &status.displays {
+        println!(
+            "display {} {}x{}+{}+{}",
+            display.ident, display.size.0, display.size.1, display.position.0, display.position.1
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Compositing render passes onto one another
+//!
+//! Follows the W3C compositing model: the blend mode mixes the colours where
+//! both layers are opaque, then the result is composited source-over.
+//! <https://www.w3.org/TR/compositing-1/>
+
+use crate::config::BlendMode;
+use image::RgbaImage;
+
+/// Composite `layer` over `base`, which must be the same size
+///
+/// Panics if the sizes differ, rather than shearing the rows of one.
+pub fn blend(base: &mut RgbaImage, layer: &RgbaImage, mode: BlendMode, opacity: f32) {
+    assert_eq!(
+        base.dimensions(),
+        layer.dimensions(),
+        "blended layers differ in size"
+    );
+
+    for (base, layer) in base.pixels_mut().zip(layer.pixels()) {
+        let alpha_s = f32::from(layer.0[3]) / 255.0 * opacity;
+        if alpha_s <= 0.0 {
+            continue;
+        }
+        let alpha_b = f32::from(base.0[3]) / 255.0;
+        let alpha = alpha_s + alpha_b * (1.0 - alpha_s);
+
+        for channel in 0..3 {
+            let cs = f32::from(layer.0[channel]) / 255.0;
+            let cb = f32::from(base.0[channel]) / 255.0;
+
+            // Blended colour shows only where the base is opaque
+            let cs = (1.0 - alpha_b) * cs + alpha_b * blend_channel(mode, cb, cs);
+            let co = (alpha_s * cs + alpha_b * cb * (1.0 - alpha_s)) / alpha;
+
+            base.0[channel] = (co * 255.0).round() as u8;
+        }
+        base.0[3] = (alpha * 255.0).round() as u8;
+    }
+}
+
+fn blend_channel(mode: BlendMode, cb: f32, cs: f32) -> f32 {
+    match mode {
+        BlendMode::Normal => cs,
+        BlendMode::Multiply => cb * cs,
+        BlendMode::Screen => cb + cs - cb * cs,
+        BlendMode::Overlay if cb <= 0.5 => 2.0 * cb * cs,
+        BlendMode::Overlay => 1.0 - 2.0 * (1.0 - cb) * (1.0 - cs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn blend_pixel(base: [u8; 4], layer: [u8; 4], mode: BlendMode, opacity: f32) -> [u8; 4] {
+        let mut base = RgbaImage::from_pixel(1, 1, Rgba(base));
+        blend(
+            &mut base,
+            &RgbaImage::from_pixel(1, 1, Rgba(layer)),
+            mode,
+            opacity,
+        );
+        base.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn normal_replaces_opaque_pixels() {
+        let pixel = blend_pixel(
+            [10, 20, 30, 255],
+            [200, 100, 50, 255],
+            BlendMode::Normal,
+            1.0,
+        );
+        assert_eq!(pixel, [200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn opacity_mixes_layers() {
+        let pixel = blend_pixel([0, 0, 0, 255], [255, 255, 255, 255], BlendMode::Normal, 0.5);
+        assert_eq!(pixel, [128, 128, 128, 255]);
+
+        let pixel = blend_pixel([0, 0, 0, 255], [255, 255, 255, 255], BlendMode::Normal, 0.0);
+        assert_eq!(pixel, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn translucent_layers_over_nothing_keep_their_colour() {
+        let pixel = blend_pixel([0, 0, 0, 0], [255, 0, 0, 255], BlendMode::Multiply, 0.5);
+        assert_eq!(pixel, [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn modes_mix_opaque_colours() {
+        let base = [255, 128, 0, 255];
+        let layer = [128, 128, 128, 255];
+
+        assert_eq!(
+            blend_pixel(base, layer, BlendMode::Multiply, 1.0),
+            [128, 64, 0, 255]
+        );
+        assert_eq!(
+            blend_pixel(base, layer, BlendMode::Screen, 1.0),
+            [255, 192, 128, 255]
+        );
+        assert_eq!(
+            blend_pixel(base, layer, BlendMode::Overlay, 1.0),
+            [255, 128, 0, 255]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "differ in size")]
+    fn layers_of_different_sizes_are_rejected() {
+        let mut base = RgbaImage::new(2, 2);
+        blend(&mut base, &RgbaImage::new(4, 1), BlendMode::Normal, 1.0);
+    }
+}
@@ -0,0 +1,31 @@
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use std::path::PathBuf;
+
+/// Wallpaper utility for wlroots-based Wayland compositors
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the config file, images are relative to its directory
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Log level: off, error, warn, info, debug or trace
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: LevelFilter,
+
+    /// Draw every display once and exit, failing if none is connected
+    #[arg(long)]
+    pub once: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Validate the config and exit
+    Check,
+    /// List connected outputs and exit
+    PrintOutputs,
+}
@@ -0,0 +1,366 @@
+//! Compositing a display's buffer on a worker
+//!
+//! Each layer is cropped from the resize of its canvas and rescaled to the
+//! display, then blended in render pass order. The result is turned to the
+//! output's orientation and converted to `wl_shm` ARGB8888, so the Wayland
+//! thread only copies it into a buffer.
+
+use crate::{
+    blend::blend,
+    config::BlendMode,
+    display::{argb8888, scale_dim},
+    mq::Message,
+    region::Region,
+    resize::ResizeKey,
+};
+use cgmath::Vector2;
+use image::{
+    imageops::{self, FilterType},
+    GenericImageView, Rgba, RgbaImage,
+};
+use log::error;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+use wayland_client::protocol::wl_output::Transform;
+
+/// Identifies what a display's composite draws, so an unchanged one being
+/// drawn isn't started again
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositeKey {
+    pub dim: Vector2<u32>,
+    pub transform: Transform,
+    /// Render pass, resize and crop of each layer
+    pub layers: Vec<(usize, ResizeKey, Region)>,
+}
+
+/// Composites being drawn by workers, by display
+///
+/// Only the latest composite started for a display is presented, and none
+/// once it is cancelled, so a result arriving after the display changed
+/// can't replace its newer image.
+#[derive(Default)]
+pub struct PendingComposites {
+    serial: u64,
+    pending: HashMap<String, (u64, CompositeKey)>,
+}
+
+impl PendingComposites {
+    /// Serial of a new composite of a display, `None` if the same composite
+    /// is already being drawn
+    pub fn start(&mut self, display: &str, key: CompositeKey) -> Option<u64> {
+        if self
+            .pending
+            .get(display)
+            .is_some_and(|(_, pending)| *pending == key)
+        {
+            return None;
+        }
+
+        self.serial += 1;
+        self.pending.insert(display.to_string(), (self.serial, key));
+        Some(self.serial)
+    }
+
+    /// Drop the result of a display's composite being drawn
+    pub fn cancel(&mut self, display: &str) {
+        self.pending.remove(display);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Whether a finished composite is the latest of its display, which is
+    /// then no longer pending
+    pub fn finish(&mut self, display: &str, serial: u64) -> bool {
+        let latest = self
+            .pending
+            .get(display)
+            .is_some_and(|(pending, _)| *pending == serial);
+        if latest {
+            self.pending.remove(display);
+        }
+        latest
+    }
+}
+
+pub enum LayerSource {
+    Resized(Arc<RgbaImage>),
+    Color([u8; 4]),
+}
+
+/// A render pass drawn onto a display
+pub struct Layer {
+    pub source: LayerSource,
+    /// Region of the canvas the resize covers
+    pub total: Region,
+    /// Region of the canvas shown by the display
+    pub crop: Region,
+    /// Scale of the resize in 120ths
+    pub scale: u32,
+    pub filter: FilterType,
+    pub blend: BlendMode,
+    pub opacity: f32,
+}
+
+/// The layers of one display, composited into a buffer on a worker
+pub struct CompositeJob {
+    pub display: String,
+    /// Distinguishes the latest composite of the display from stale ones
+    pub serial: u64,
+    /// Size of the display's buffer before the output transform
+    pub dim: Vector2<u32>,
+    pub transform: Transform,
+    /// In render pass order, never empty
+    pub layers: Vec<Layer>,
+}
+
+impl CompositeJob {
+    /// Composite the layers, reporting no buffer if compositing panics
+    pub fn run(self) -> Message {
+        let buffer = panic::catch_unwind(AssertUnwindSafe(|| self.composite()));
+        if buffer.is_err() {
+            error!("failed to composite display '{}'", self.display);
+        }
+
+        Message::Composited {
+            display: self.display,
+            serial: self.serial,
+            buffer: buffer.ok(),
+        }
+    }
+
+    /// Size and ARGB8888 pixels of the buffer
+    fn composite(&self) -> (Vector2<u32>, Vec<u8>) {
+        let mut composite: Option<Cow<RgbaImage>> = None;
+        for layer in &self.layers {
+            let image = match &layer.source {
+                LayerSource::Resized(resized) => crop(
+                    resized,
+                    layer.total,
+                    layer.crop,
+                    layer.scale,
+                    self.dim,
+                    layer.filter,
+                ),
+                LayerSource::Color(color) => {
+                    Cow::Owned(RgbaImage::from_pixel(self.dim.x, self.dim.y, Rgba(*color)))
+                }
+            };
+
+            let opaque = layer.blend == BlendMode::Normal && layer.opacity >= 1.0;
+            match &mut composite {
+                Some(base) => blend(base.to_mut(), &image, layer.blend, layer.opacity),
+                None if opaque => composite = Some(image),
+                None => {
+                    let mut base = RgbaImage::new(self.dim.x, self.dim.y);
+                    blend(&mut base, &image, layer.blend, layer.opacity);
+                    composite = Some(Cow::Owned(base));
+                }
+            }
+        }
+        let composite =
+            composite.unwrap_or_else(|| Cow::Owned(RgbaImage::new(self.dim.x, self.dim.y)));
+
+        // Buffers are in the output's orientation so compositors needn't rotate them
+        let image = match self.transform {
+            Transform::Normal => composite,
+            transform => Cow::Owned(transform_image(&composite, transform)),
+        };
+
+        let dim = Vector2::from(image.dimensions());
+        (dim, image.pixels().flat_map(|p| argb8888(*p)).collect())
+    }
+}
+
+/// Part of an image covering the total region shown by a display
+///
+/// The image is in physical pixels at the given scale in 120ths, and is
+/// rescaled if the display's buffer differs in size. Rounding at fractional
+/// scales can leave the crop a pixel short of the image's edge, which is
+/// rescaled the same way.
+fn crop(
+    image: &RgbaImage,
+    total: Region,
+    crop: Region,
+    scale: u32,
+    dim: Vector2<u32>,
+    filter: FilterType,
+) -> Cow<'_, RgbaImage> {
+    let offset = scale_dim(crop.min - total.min, scale);
+    let size = scale_dim(crop.dim, scale);
+
+    if crop == total && Vector2::from(image.dimensions()) == dim {
+        return Cow::Borrowed(image);
+    }
+
+    // Clamped to the image, so may be smaller than requested
+    let image = imageops::crop_imm(image, offset.x, offset.y, size.x, size.y);
+    if Vector2::from(image.dimensions()) == dim {
+        Cow::Owned(image.to_image())
+    } else {
+        Cow::Owned(imageops::resize(&*image, dim.x, dim.y, filter))
+    }
+}
+
+/// Apply an output transform, flipping horizontally and then rotating counter-clockwise
+fn transform_image(image: &RgbaImage, transform: Transform) -> RgbaImage {
+    let flipped = matches!(
+        transform,
+        Transform::Flipped | Transform::Flipped90 | Transform::Flipped180 | Transform::Flipped270
+    );
+    let flipped = flipped.then(|| imageops::flip_horizontal(image));
+    let image = flipped.as_ref().unwrap_or(image);
+
+    match transform {
+        Transform::_90 | Transform::Flipped90 => imageops::rotate270(image),
+        Transform::_180 | Transform::Flipped180 => imageops::rotate180(image),
+        Transform::_270 | Transform::Flipped270 => imageops::rotate90(image),
+        _ => image.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Anchor, Background, Resize, ResizeKind};
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn region(x: i32, width: i32) -> Region {
+        Region::new(Vector2::new(x, 0), Vector2::new(x + width, 1))
+    }
+
+    fn layer(source: LayerSource, crop: Region) -> Layer {
+        Layer {
+            source,
+            total: region(0, 2),
+            crop,
+            scale: 120,
+            filter: FilterType::Nearest,
+            blend: BlendMode::Normal,
+            opacity: 1.0,
+        }
+    }
+
+    fn run(dim: Vector2<u32>, transform: Transform, layers: Vec<Layer>) -> (Vector2<u32>, Vec<u8>) {
+        let job = CompositeJob {
+            display: "DP-1".to_string(),
+            serial: 1,
+            dim,
+            transform,
+            layers,
+        };
+        match job.run() {
+            Message::Composited {
+                buffer: Some(buffer),
+                ..
+            } => buffer,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn crops_the_display_from_the_canvas() {
+        let canvas = RgbaImage::from_fn(2, 1, |x, _| Rgba(if x == 0 { RED } else { BLUE }));
+        let resized = Arc::new(canvas);
+
+        let left = layer(LayerSource::Resized(resized.clone()), region(0, 1));
+        let (_, pixels) = run(Vector2::new(1, 1), Transform::Normal, vec![left]);
+        assert_eq!(pixels, argb8888(Rgba(RED)));
+
+        let right = layer(LayerSource::Resized(resized), region(1, 1));
+        let (_, pixels) = run(Vector2::new(1, 1), Transform::Normal, vec![right]);
+        assert_eq!(pixels, argb8888(Rgba(BLUE)));
+    }
+
+    #[test]
+    fn layers_are_blended_in_order() {
+        let mut half = layer(LayerSource::Color(BLUE), region(0, 2));
+        half.opacity = 0.5;
+        let layers = vec![layer(LayerSource::Color(RED), region(0, 2)), half];
+
+        let (dim, pixels) = run(Vector2::new(2, 1), Transform::Normal, layers);
+        assert_eq!(dim, Vector2::new(2, 1));
+        assert_eq!(pixels[..4], argb8888(Rgba([128, 0, 128, 255])));
+    }
+
+    #[test]
+    fn buffers_are_in_the_output_orientation() {
+        let canvas = RgbaImage::from_fn(2, 1, |x, _| Rgba(if x == 0 { RED } else { BLUE }));
+        let layers = vec![layer(LayerSource::Resized(Arc::new(canvas)), region(0, 2))];
+
+        let (dim, pixels) = run(Vector2::new(2, 1), Transform::_90, layers);
+        assert_eq!(dim, Vector2::new(1, 2));
+        assert_eq!(pixels[..4], argb8888(Rgba(BLUE)));
+        assert_eq!(pixels[4..], argb8888(Rgba(RED)));
+    }
+
+    fn composite_key(image: &str) -> CompositeKey {
+        let resize = Resize {
+            kind: ResizeKind::Cover,
+            filter: Default::default(),
+            anchor: Anchor::Center,
+            background: Background::Transparent,
+            tile_scale: 1,
+        };
+        let key = ResizeKey {
+            image: image.to_string(),
+            region: region(0, 2),
+            scale: 120,
+            resize,
+        };
+        CompositeKey {
+            dim: Vector2::new(2, 1),
+            transform: Transform::Normal,
+            layers: vec![(0, key, region(0, 2))],
+        }
+    }
+
+    #[test]
+    fn unchanged_composites_are_not_started_again() {
+        let mut composites = PendingComposites::default();
+        let first = composites.start("DP-1", composite_key("a")).unwrap();
+        assert_eq!(composites.start("DP-1", composite_key("a")), None);
+
+        // A changed composite replaces the one being drawn
+        let second = composites.start("DP-1", composite_key("b")).unwrap();
+        assert!(!composites.finish("DP-1", first));
+        assert!(composites.finish("DP-1", second));
+        assert!(!composites.finish("DP-1", second));
+    }
+
+    #[test]
+    fn composites_finishing_after_a_rotation_are_dropped() {
+        let mut composites = PendingComposites::default();
+        let before = composites.start("DP-1", composite_key("a")).unwrap();
+
+        // The display rotates while compositing, and waits for its new resize
+        composites.cancel("DP-1");
+        assert!(!composites.finish("DP-1", before));
+
+        // Once resized, the new image is composited and presented
+        let after = composites.start("DP-1", composite_key("b")).unwrap();
+        assert!(composites.finish("DP-1", after));
+    }
+
+    #[test]
+    fn crops_past_the_image_edge_are_rescaled_to_the_display() {
+        // Two displays 1 px wide at scale 1.5 share a 3 px canvas, rounding
+        // puts the second at 2 px for 2 px though only 1 px remains
+        let canvas = RgbaImage::from_fn(3, 1, |x, _| Rgba(if x < 2 { RED } else { BLUE }));
+        let mut right = layer(LayerSource::Resized(Arc::new(canvas)), region(1, 1));
+        right.scale = 180;
+        let mut base = layer(LayerSource::Color(RED), region(1, 1));
+        base.scale = 180;
+
+        let (dim, pixels) = run(Vector2::new(2, 1), Transform::Normal, vec![base, right]);
+        assert_eq!(dim, Vector2::new(2, 1));
+        assert_eq!(pixels, [argb8888(Rgba(BLUE)); 2].concat());
+    }
+}
@@ -1,44 +1,140 @@
-use image::{ImageReader, RgbaImage};
+use crate::{
+    decode::DEFAULT_MEMORY_BUDGET,
+    disk_cache::DEFAULT_DISK_BUDGET,
+    gradient::{ColorStop, Gradient, GradientKind},
+    matcher::Matcher,
+    scan::{self, Scan},
+    xdg,
+};
+use cgmath::Vector2;
+use image::{imageops::FilterType, ImageReader};
 use log::{error, info, warn};
 use rand::random_range;
 use serde::Deserialize;
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 use toml::{Table, Value};
 
 #[derive(Debug, Default)]
 pub struct Config {
+    pub path: PathBuf,
     pub images: HashMap<String, LoadedImage>,
+    /// Images added from a directory or glob, by its ident or path
+    pub image_sets: HashMap<String, Vec<String>>,
+    /// Directories searched for images, rescanned when they change
+    pub scanned_dirs: HashSet<PathBuf>,
+    /// Bytes kept for decoded images
+    pub memory_budget: u64,
+    /// Bytes kept for resized images on disk, 0 disables the disk cache
+    pub disk_budget: u64,
     pub displays: HashMap<String, DisplayTarget>,
     pub groups: HashMap<String, DisplayGroup>,
     pub render_passes: Vec<RenderPass>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoadedImage {
-    pub image: RgbaImage,
+    pub source: ImageSource,
+    /// Normalized point kept in view when cropping
+    pub focus: Option<Vector2<f64>>,
 }
 
-#[derive(Debug)]
+/// Pixels of an image, decoded from a file or generated at any size
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    /// Decoded when drawn, see [`crate::decode`]
+    File {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+        dim: Vector2<u32>,
+    },
+    Color([u8; 4]),
+    Gradient(Gradient),
+}
+
+/// Output properties identifying a display, see [`crate::matcher`]
+///
+/// An entry whose matchers are all unset or match everything, like
+/// `name = "*"`, is a fallback drawing to every output no other entry claims.
+/// It may still set the keys describing the display itself, `bezel` and `dpi`.
+#[derive(Debug, Default, PartialEq, Deserialize)]
 pub struct DisplayTarget {
-    pub name: String,
+    #[serde(default)]
+    pub name: Option<Matcher>,
+    #[serde(default)]
+    pub make: Option<Matcher>,
+    #[serde(default)]
+    pub model: Option<Matcher>,
+    #[serde(default)]
+    pub serial: Option<Matcher>,
+    #[serde(default)]
+    pub description: Option<Matcher>,
+
+    #[serde(default)]
+    pub bezel: Bezel,
+    /// Physical pixels per inch, overriding the size reported by the output
+    #[serde(default)]
+    pub dpi: Option<f64>,
 }
 
-#[derive(Debug)]
+/// Border hiding the edges of the image when spanning a group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Bezel {
+    #[serde(default)]
+    pub left: f64,
+    #[serde(default)]
+    pub right: f64,
+    #[serde(default)]
+    pub top: f64,
+    #[serde(default)]
+    pub bottom: f64,
+    #[serde(default)]
+    pub unit: BezelUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BezelUnit {
+    /// Logical pixels
+    #[default]
+    Px,
+    /// Millimetres, using the physical size reported by the output
+    Mm,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct DisplayGroup {
-    pub displays: Vec<String>,
+    pub displays: GroupDisplays,
+    /// Space between displays in logical pixels, in addition to bezels
+    pub gap: i32,
+    /// Size displays by their physical size rather than logical pixels
+    pub physical_size: bool,
+    /// Display positions overriding those of the compositor
+    pub layout: HashMap<String, Vector2<i32>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+pub enum GroupDisplays {
+    /// Every connected output, set with `displays = "*"`
+    All,
+    Listed(Vec<String>),
+}
+
+#[derive(Debug, PartialEq)]
 pub struct RenderPass {
+    pub name: Option<String>,
     pub source: RenderSource,
     pub target: RenderTarget,
-    pub resize: ResizeKind,
+    pub resize: Resize,
+    /// Opacity when composited over earlier render passes, from 0 to 1
+    pub opacity: f32,
+    pub blend: BlendMode,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum RenderSource {
     Single(String),
     Many {
@@ -54,28 +150,174 @@ pub enum RenderTarget {
     Group(String),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+/// How a render pass fits its image to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Resize {
+    pub kind: ResizeKind,
+    pub filter: ResizeFilter,
+    pub anchor: Anchor,
+    pub background: Background,
+    /// Integer scale applied to tiles
+    pub tile_scale: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ResizeKind {
     #[default]
     Cover,
     Stretch,
+    Contain,
+    /// Repeat the image from the top-left
+    Tile,
+    /// Unscaled in the centre
+    Center,
+    /// Unscaled in the top-left
+    None,
+}
+
+/// Edge or corner of the image kept in view when it does not fit the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Position along each axis, 0 at the top-left and 1 at the bottom-right
+    pub fn fraction(self) -> Vector2<f64> {
+        let (x, y) = match self {
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        };
+        Vector2::new(x, y)
+    }
 }
 
+/// Resampling filter used when scaling images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeFilter {
+    /// Sharp pixels, best for pixel art
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(value: ResizeFilter) -> Self {
+        match value {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// How a render pass is combined with the render passes before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+}
+
+/// Fill for parts of the target not covered by the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Background {
+    /// Solid RGBA colour
+    Color([u8; 4]),
+    /// Blurred copy of the image, resized to cover the target
+    Blur,
+    Transparent,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color([0, 0, 0, 255])
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "blur" => Ok(Background::Blur),
+            "transparent" => Ok(Background::Transparent),
+            color => parse_color(color)
+                .map(Background::Color)
+                .ok_or_else(|| format!("invalid background '{value}', expected 'blur', 'transparent' or a '#rrggbb' colour")),
+        }
+    }
+}
+
+/// Parse a `#rrggbb` or `#rrggbbaa` colour
+pub fn parse_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#')?;
+    // Checked first, as `from_str_radix` also accepts a sign
+    if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+        if hex.len() == 8 { channel(6)? } else { 255 },
+    ])
+}
+
+#[derive(Debug)]
 pub enum ConfigError {
     MissingConfig(PathBuf),
+    /// Neither `$XDG_CONFIG_HOME` or `$HOME` are set
+    NoConfigDirectory,
 
     Io(std::io::Error),
     Toml(toml::de::Error),
     Image(image::error::ImageError),
+    Glob(globset::Error),
 
     /// Ident is both a display and a group
     AmbiguousRenderTarget(String),
     /// Render target cannot be found
     UnknownRenderTarget(String),
+    /// Render pass name is used more than once
+    DuplicateRenderPass(String),
+    /// Render pass name would be read as an index
+    NumericRenderPass(String),
 
     /// Image could not be found
     UnknownImage(String),
+    /// Image source is missing or malformed
+    InvalidImage(String, String),
     /// Display could not be found
     UnknownDisplay(String),
     /// Group could not be found
@@ -86,22 +328,170 @@ pub enum ConfigError {
 }
 
 impl Config {
-    pub fn load() -> Result<Config, ConfigError> {
-        // Create path to config directory
-        let mut wani_path = std::env::home_dir().expect("Failed to get home directory");
-        wani_path.push(".config/wani");
-        std::fs::create_dir_all(&wani_path).expect("Failed to create config directory");
-        info!("wanipaper directory {:?}", wani_path);
+    /// Locate `wani/wanipaper.config` in the XDG config directories
+    ///
+    /// `$XDG_CONFIG_HOME` is searched before `$XDG_CONFIG_DIRS`, so a user
+    /// config takes priority over a system-wide default.
+    pub fn default_path() -> Result<PathBuf, ConfigError> {
+        let candidates = xdg::config_home()
+            .into_iter()
+            .chain(xdg::config_dirs())
+            .map(|dir| dir.join("wani/wanipaper.config"))
+            .collect::<Vec<_>>();
 
-        let mut config_path = wani_path.clone();
-        config_path.push("wanipaper.config");
+        match candidates.iter().find(|path| path.exists()) {
+            Some(path) => Ok(path.clone()),
+            None => match candidates.into_iter().next() {
+                Some(path) => Err(ConfigError::MissingConfig(path)),
+                None => Err(ConfigError::NoConfigDirectory),
+            },
+        }
+    }
+
+    /// Resolve an image path
+    ///
+    /// Relative paths are searched for in the config directory, then in
+    /// `wani` under `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`.
+    fn image_path(wani_path: &Path, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+
+        let config_relative = wani_path.join(path);
+        std::iter::once(config_relative.clone())
+            .chain(
+                xdg::data_home()
+                    .into_iter()
+                    .chain(xdg::data_dirs())
+                    .map(|dir| dir.join("wani").join(path)),
+            )
+            .find(|path| path.exists())
+            .unwrap_or(config_relative)
+    }
+
+    /// Whether a path is a glob, rather than an existing file named with wildcards
+    fn is_glob(wani_path: &Path, path: &Path) -> bool {
+        scan::is_glob(path) && !Config::image_path(wani_path, path).exists()
+    }
+
+    /// Expand a directory or glob, `None` for any other path
+    fn scan(wani_path: &Path, path: &Path, recursive: bool) -> Result<Option<Scan>, ConfigError> {
+        if Config::is_glob(wani_path, path) {
+            let (root, pattern) = scan::split_glob(path);
+            let root = Config::image_path(wani_path, &root);
+            return scan::scan_glob(&root, &pattern)
+                .map(Some)
+                .map_err(ConfigError::Glob);
+        }
+
+        let path = Config::image_path(wani_path, path);
+        Ok(path.is_dir().then(|| scan::scan_dir(&path, recursive)))
+    }
+
+    /// Check an image file can be decoded by reading only its header
+    fn probe_image(path: &Path) -> Result<ImageSource, ConfigError> {
+        info!("load image {:?}", path);
+
+        let (width, height) = ImageReader::open(path)
+            .map_err(ConfigError::Io)?
+            .with_guessed_format()
+            .map_err(ConfigError::Io)?
+            .into_dimensions()
+            .map_err(ConfigError::Image)?;
+
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        Ok(ImageSource::File {
+            path: path.to_path_buf(),
+            modified,
+            dim: Vector2::new(width, height),
+        })
+    }
+
+    /// Add an image for each file of a scan, named `{prefix}/{relative path}`
+    ///
+    /// Files already added by another set are shared rather than added again.
+    fn add_image_set(
+        &mut self,
+        ident: String,
+        prefix: &Path,
+        scan: Scan,
+        focus: Option<Vector2<f64>>,
+    ) {
+        if scan.files.is_empty() {
+            warn!("no images found for '{ident}'");
+        }
+
+        let named = scan
+            .files
+            .iter()
+            .map(|file| {
+                let relative = file.strip_prefix(&scan.root).unwrap_or(file);
+                (prefix.join(relative).to_string_lossy().into_owned(), file)
+            })
+            .collect::<Vec<_>>();
+
+        // Headers of large directories are read on several threads
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_len = named.len().div_ceil(threads).max(1);
+        let probed = std::thread::scope(|scope| {
+            let chunks = named
+                .chunks(chunk_len)
+                .map(|chunk| {
+                    scope.spawn(|| {
+                        chunk
+                            .iter()
+                            .map(|(member, file)| {
+                                (!self.images.contains_key(member))
+                                    .then(|| Config::probe_image(file))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            chunks
+                .into_iter()
+                .flat_map(|chunk| chunk.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut members = Vec::new();
+        for ((member, _), probe) in named.into_iter().zip(probed) {
+            let Some(probe) = probe else {
+                members.push(member);
+                continue;
+            };
+
+            match probe {
+                Ok(source) => {
+                    self.images
+                        .insert(member.clone(), LoadedImage { source, focus });
+                    members.push(member);
+                }
+                Err(e) => {
+                    error!("failed to load image '{member}'");
+                    error!("{e}");
+                }
+            }
+        }
+
+        self.image_sets.insert(ident, members);
+        self.scanned_dirs.extend(scan.dirs);
+    }
+
+    pub fn load(config_path: &Path) -> Result<Config, ConfigError> {
         info!("wanipaper config path {:?}", config_path);
 
         if !config_path.exists() {
-            return Err(ConfigError::MissingConfig(config_path.clone()));
+            return Err(ConfigError::MissingConfig(config_path.to_path_buf()));
         }
 
-        info!("load path {:?}", config_path);
+        // Images are relative to the config directory
+        let wani_path = config_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        info!("wanipaper directory {:?}", wani_path);
 
         // Read config file
         let config_file = std::fs::read_to_string(config_path).map_err(ConfigError::Io)?;
@@ -110,39 +500,155 @@ impl Config {
         let mut table: Table = toml::from_str(&config_file).map_err(ConfigError::Toml)?;
 
         // Create default config
-        let mut config = Config::default();
+        let mut config = Config {
+            path: config_path.to_path_buf(),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            disk_budget: DEFAULT_DISK_BUDGET,
+            ..Default::default()
+        };
+
+        // Load Cache Settings
+        {
+            #[derive(Deserialize)]
+            struct CacheConfig {
+                /// Mebibytes of decoded images to keep
+                #[serde(default)]
+                memory: Option<u64>,
+                /// Mebibytes of resized images to keep on disk
+                #[serde(default)]
+                disk: Option<u64>,
+            }
+
+            if let Some(cache) = table.remove("cache") {
+                let cache: CacheConfig = cache.try_into().map_err(ConfigError::Toml)?;
+                if let Some(memory) = cache.memory {
+                    config.memory_budget = memory * 1024 * 1024;
+                }
+                if let Some(disk) = cache.disk {
+                    config.disk_budget = disk * 1024 * 1024;
+                }
+            }
+        }
 
         // Load Images
         {
             #[derive(Deserialize)]
             struct ImageConfig {
-                path: String,
+                #[serde(default)]
+                path: Option<PathBuf>,
+                #[serde(default)]
+                recursive: bool,
+                #[serde(default)]
+                color: Option<String>,
+                #[serde(default)]
+                gradient: Option<GradientKind>,
+                /// CSS default, top to bottom
+                #[serde(default = "default_angle")]
+                angle: f64,
+                #[serde(default)]
+                stops: Vec<StopConfig>,
+                #[serde(default)]
+                focus: Option<[f64; 2]>,
+            }
+
+            fn default_angle() -> f64 {
+                180.0
+            }
+
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum StopConfig {
+                Color(String),
+                Positioned { color: String, position: f64 },
             }
 
             if let Some(Value::Table(images)) = table.remove("images") {
                 for (ident, image) in images {
                     let image_config: ImageConfig = image.try_into().map_err(ConfigError::Toml)?;
 
-                    let mut image_path = wani_path.clone();
-                    image_path.push(image_config.path);
-                    info!("load image {:?}", image_path);
+                    let focus = image_config.focus.map(|[x, y]| {
+                        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+                            warn!("image '{ident}' focus should be between 0 and 1, clamped");
+                        }
+                        Vector2::new(x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))
+                    });
+
+                    // Directories and globs add an image per file
+                    if let Some(path) = &image_config.path {
+                        match Config::scan(&wani_path, path, image_config.recursive) {
+                            Ok(Some(scan)) => {
+                                config.add_image_set(ident.clone(), Path::new(&ident), scan, focus);
+                                continue;
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                error!("failed to load image '{ident}'");
+                                error!("{e}");
+                                continue;
+                            }
+                        }
+                    }
 
-                    let loaded_image = || -> Result<RgbaImage, ConfigError> {
-                        Ok(ImageReader::open(&image_path)
-                            .map_err(ConfigError::Io)?
-                            .with_guessed_format()
-                            .map_err(ConfigError::Io)?
-                            .decode()
-                            .map_err(ConfigError::Image)?
-                            .into_rgba8())
-                    }();
+                    let invalid =
+                        |reason: &str| ConfigError::InvalidImage(ident.clone(), reason.to_string());
+                    let color = |color: &str| {
+                        parse_color(color)
+                            .ok_or_else(|| invalid(&format!("has invalid colour '{color}'")))
+                    };
+
+                    let loaded_image = match (
+                        &image_config.path,
+                        &image_config.color,
+                        image_config.gradient,
+                    ) {
+                        (Some(path), None, None) => {
+                            Config::probe_image(&Config::image_path(&wani_path, path))
+                        }
+                        (None, Some(c), None) => color(c).map(ImageSource::Color),
+                        (None, None, Some(kind)) => (|| {
+                            let count = image_config.stops.len();
+                            if count == 0 {
+                                return Err(invalid("gradient has no stops"));
+                            }
+
+                            // Stops without a position are spread evenly
+                            let mut stops = image_config
+                                .stops
+                                .iter()
+                                .enumerate()
+                                .map(|(i, stop)| {
+                                    let even = i as f64 / (count - 1).max(1) as f64;
+                                    Ok(match stop {
+                                        StopConfig::Color(c) => ColorStop {
+                                            color: color(c)?,
+                                            position: even,
+                                        },
+                                        StopConfig::Positioned { color: c, position } => {
+                                            ColorStop {
+                                                color: color(c)?,
+                                                position: position.clamp(0.0, 1.0),
+                                            }
+                                        }
+                                    })
+                                })
+                                .collect::<Result<Vec<_>, ConfigError>>()?;
+                            stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+                            Ok(ImageSource::Gradient(Gradient {
+                                kind,
+                                angle: image_config.angle,
+                                stops,
+                            }))
+                        })(),
+                        _ => Err(invalid("needs exactly one of path, color or gradient")),
+                    };
 
                     match loaded_image {
-                        Ok(image) => {
-                            config.images.insert(ident, LoadedImage { image });
+                        Ok(source) => {
+                            config.images.insert(ident, LoadedImage { source, focus });
                         }
                         Err(e) => {
-                            error!("failed to load image: {:?}", &image_path);
+                            error!("failed to load image '{ident}'");
                             error!("{e}");
                         }
                     }
@@ -152,18 +658,11 @@ impl Config {
 
         // Load Displays
         {
-            #[derive(Deserialize)]
-            struct DisplayConfig {
-                name: String,
-            }
-
             if let Some(Value::Table(displays)) = table.remove("displays") {
                 for (ident, display) in displays {
-                    let display: DisplayConfig = display.try_into().map_err(ConfigError::Toml)?;
+                    let display: DisplayTarget = display.try_into().map_err(ConfigError::Toml)?;
 
-                    config
-                        .displays
-                        .insert(ident, DisplayTarget { name: display.name });
+                    config.displays.insert(ident, display);
                 }
             }
         }
@@ -172,36 +671,79 @@ impl Config {
         {
             #[derive(Deserialize)]
             struct GroupConfig {
-                displays: Vec<String>,
+                displays: GroupDisplaysConfig,
+                #[serde(default)]
+                gap: i32,
+                #[serde(default)]
+                physical_size: bool,
+                #[serde(default)]
+                layout: HashMap<String, [i32; 2]>,
+            }
+
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum GroupDisplaysConfig {
+                All(String),
+                Listed(Vec<String>),
             }
 
             if let Some(Value::Table(groups)) = table.remove("groups") {
                 for (ident, group) in groups {
-                    let mut group: GroupConfig = group.try_into().map_err(ConfigError::Toml)?;
+                    let group: GroupConfig = group.try_into().map_err(ConfigError::Toml)?;
+
+                    let displays = match group.displays {
+                        GroupDisplaysConfig::All(all) if all == "*" => GroupDisplays::All,
+                        GroupDisplaysConfig::All(display) => GroupDisplays::Listed(vec![display]),
+                        GroupDisplaysConfig::Listed(displays) => GroupDisplays::Listed(displays),
+                    };
 
-                    group.displays = group
-                        .displays
+                    let displays = match displays {
+                        GroupDisplays::All => GroupDisplays::All,
+                        GroupDisplays::Listed(mut displays) => {
+                            displays.retain(|display| {
+                                if !config.displays.contains_key(display) {
+                                    warn!(
+                                        "display '{display}' not found, removed from group '{ident}'"
+                                    );
+                                    return false;
+                                }
+                                true
+                            });
+
+                            if displays.is_empty() {
+                                error!("group '{ident}' contains no displays, group removed");
+                                continue;
+                            }
+
+                            GroupDisplays::Listed(displays)
+                        }
+                    };
+
+                    let layout = group
+                        .layout
                         .into_iter()
-                        .filter(|display| {
-                            if !config.displays.contains_key(display) {
+                        .filter(|(display, _)| {
+                            let member = match &displays {
+                                GroupDisplays::All => config.displays.contains_key(display),
+                                GroupDisplays::Listed(displays) => displays.contains(display),
+                            };
+                            if !member {
                                 warn!(
-                                    "display '{display}' not found, removed from group '{ident}'"
+                                    "display '{display}' is not in group '{ident}', layout ignored"
                                 );
-                                return false;
                             }
-                            true
+                            member
                         })
+                        .map(|(display, [x, y])| (display, Vector2::new(x, y)))
                         .collect();
 
-                    if group.displays.is_empty() {
-                        error!("group '{ident}' contains no displays, group removed");
-                        continue;
-                    }
-
                     config.groups.insert(
                         ident,
                         DisplayGroup {
-                            displays: group.displays,
+                            displays,
+                            gap: group.gap,
+                            physical_size: group.physical_size,
+                            layout,
                         },
                     );
                 }
@@ -212,12 +754,26 @@ impl Config {
         {
             #[derive(Debug, Deserialize)]
             pub struct RenderConfig {
+                #[serde(default)]
+                name: Option<String>,
                 source: OneOrMany<String>,
                 #[serde(default)]
                 selection: Option<SelectionConfig>,
                 target: String,
                 #[serde(default)]
                 resize: ResizeKind,
+                #[serde(default)]
+                filter: ResizeFilter,
+                #[serde(default)]
+                anchor: Anchor,
+                #[serde(default)]
+                background: Background,
+                #[serde(default)]
+                tile_scale: Option<u32>,
+                #[serde(default)]
+                opacity: Option<f32>,
+                #[serde(default)]
+                blend: BlendMode,
             }
 
             #[derive(Clone, Debug, Deserialize, PartialEq)]
@@ -242,45 +798,64 @@ impl Config {
                     let render_pass: RenderConfig =
                         render_pass.try_into().map_err(ConfigError::Toml)?;
 
-                    let source = match render_pass.source {
-                        OneOrMany::One(image) => {
-                            if !config.images.contains_key(&image) {
-                                error!("image '{image}' not found, removing renderpass");
-                                continue;
+                    let (sources, single) = match render_pass.source {
+                        OneOrMany::One(image) => (vec![image], true),
+                        OneOrMany::Vec(images) => (images, false),
+                    };
+
+                    // Sources are images, image sets, or a directory or glob
+                    // scanned as a set named by its path
+                    let mut images = Vec::new();
+                    let mut expanded = false;
+                    for source in sources {
+                        if config.images.contains_key(&source) {
+                            images.push(source);
+                            continue;
+                        }
+
+                        if !config.image_sets.contains_key(&source) {
+                            match Config::scan(&wani_path, Path::new(&source), false) {
+                                Ok(Some(scan)) => {
+                                    // Named by the directory as written, so paths share images
+                                    let source_path = Path::new(&source);
+                                    let prefix = match Config::is_glob(&wani_path, source_path) {
+                                        true => scan::split_glob(source_path).0,
+                                        false => source_path.to_path_buf(),
+                                    };
+                                    config.add_image_set(source.clone(), &prefix, scan, None);
+                                }
+                                Ok(None) => {}
+                                Err(e) => error!("{e}"),
                             }
-                            RenderSource::Single(image)
                         }
-                        OneOrMany::Vec(mut images) => {
-                            images = images
-                                .into_iter()
-                                .filter(|image| {
-                                    if !config.images.contains_key(image) {
-                                        warn!("image '{image}' not found, removing renderpass");
-                                        return false;
-                                    }
-                                    true
-                                })
-                                .collect();
 
-                            if images.is_empty() {
-                                error!("renderpass contains no sources, removed");
-                                continue;
+                        match config.image_sets.get(&source) {
+                            Some(set) => {
+                                images.extend(set.iter().cloned());
+                                expanded = true;
                             }
+                            None => warn!("image '{source}' not found"),
+                        }
+                    }
 
-                            match render_pass.selection {
-                                None => RenderSource::Single(images[0].clone()),
-                                Some(SelectionConfig { rand, rotate }) => {
-                                    if rotate.is_none() && rand {
-                                        let image = images[random_range(0..images.len())].clone();
-                                        info!("selected random image '{image}'");
-                                        RenderSource::Single(image)
-                                    } else {
-                                        RenderSource::Many {
-                                            images,
-                                            rand,
-                                            rotate,
-                                        }
-                                    }
+                    if images.is_empty() {
+                        error!("renderpass contains no sources, removed");
+                        continue;
+                    }
+
+                    let source = match render_pass.selection {
+                        _ if single && !expanded => RenderSource::Single(images.remove(0)),
+                        None => RenderSource::Single(images.remove(0)),
+                        Some(SelectionConfig { rand, rotate }) => {
+                            if rotate.is_none() && rand {
+                                let image = images[random_range(0..images.len())].clone();
+                                info!("selected random image '{image}'");
+                                RenderSource::Single(image)
+                            } else {
+                                RenderSource::Many {
+                                    images,
+                                    rand,
+                                    rotate,
                                 }
                             }
                         }
@@ -301,10 +876,39 @@ impl Config {
                         }
                     };
 
+                    if let Some(name) = &render_pass.name
+                        && config
+                            .render_passes
+                            .iter()
+                            .any(|p| p.name.as_ref() == Some(name))
+                    {
+                        return Err(ConfigError::DuplicateRenderPass(name.clone()));
+                    }
+                    // Selectors parsing as a number always refer to an index
+                    if let Some(name) = &render_pass.name
+                        && name.parse::<usize>().is_ok()
+                    {
+                        return Err(ConfigError::NumericRenderPass(name.clone()));
+                    }
+
+                    let opacity = render_pass.opacity.unwrap_or(1.0);
+                    if !(0.0..=1.0).contains(&opacity) {
+                        warn!("renderpass opacity should be between 0 and 1, clamped");
+                    }
+
                     config.render_passes.push(RenderPass {
+                        name: render_pass.name,
                         source,
                         target,
-                        resize: render_pass.resize,
+                        resize: Resize {
+                            kind: render_pass.resize,
+                            filter: render_pass.filter,
+                            anchor: render_pass.anchor,
+                            background: render_pass.background,
+                            tile_scale: render_pass.tile_scale.unwrap_or(1).max(1),
+                        },
+                        opacity: opacity.clamp(0.0, 1.0),
+                        blend: render_pass.blend,
                     });
                 }
             }
@@ -320,36 +924,48 @@ impl Config {
             return Err(ConfigError::NoRenderPasses);
         }
 
-        // Remove unused images
-        {
-            let mut image_uses = HashSet::new();
-            for pass in config.render_passes.iter() {
-                match &pass.source {
-                    RenderSource::Single(image) => {
-                        image_uses.insert(image);
-                    }
-                    RenderSource::Many { images, .. } => {
-                        for image in images {
-                            image_uses.insert(image);
-                        }
-                    }
-                }
-            }
-            config.images = config
-                .images
-                .into_iter()
-                .filter(|(ident, _)| {
-                    if image_uses.contains(ident) {
-                        true
-                    } else {
-                        info!("image '{ident}' unused, removed");
-                        false
-                    }
-                })
-                .collect();
+        // Images no render pass uses are kept, as `wanictl set` can show them
+        Ok(config)
+    }
+
+    /// Files which should trigger a reload when changed
+    pub fn watch_paths(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.path.as_path())
+            .chain(self.images.values().filter_map(LoadedImage::path))
+    }
+
+    /// Directories which should trigger a reload when images are added or removed
+    pub fn watch_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.scanned_dirs.iter().map(PathBuf::as_path)
+    }
+}
+
+impl LoadedImage {
+    /// Path of an image decoded from a file
+    pub fn path(&self) -> Option<&Path> {
+        match &self.source {
+            ImageSource::File { path, .. } => Some(path),
+            _ => None,
         }
+    }
 
-        return Ok(config);
+    /// Whether the image needs redrawing after a reload
+    pub fn differs(&self, other: &LoadedImage) -> bool {
+        let source = match (&self.source, &other.source) {
+            (
+                ImageSource::File { path, modified, .. },
+                ImageSource::File {
+                    path: other_path,
+                    modified: other_modified,
+                    ..
+                },
+            ) => path != other_path || modified != other_modified,
+            (ImageSource::Color(a), ImageSource::Color(b)) => a != b,
+            (ImageSource::Gradient(a), ImageSource::Gradient(b)) => a != b,
+            _ => true,
+        };
+
+        source || self.focus != other.focus
     }
 }
 
@@ -357,15 +973,145 @@ impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ConfigError::MissingConfig(p) => write!(f, "missing config: {p:?}"),
+            ConfigError::NoConfigDirectory => {
+                write!(f, "no config directory, set $XDG_CONFIG_HOME or $HOME")
+            }
             ConfigError::Toml(e) => write!(f, "{e}"),
             ConfigError::Io(e) => write!(f, "io error: {e}"),
             ConfigError::Image(e) => write!(f, "image error: {e}"),
+            ConfigError::Glob(e) => write!(f, "glob error: {e}"),
             ConfigError::AmbiguousRenderTarget(s) => write!(f, "render yarget '{s}' is ambiguous"),
             ConfigError::UnknownRenderTarget(s) => write!(f, "'{s}' is neither a Display or Group"),
+            ConfigError::DuplicateRenderPass(s) => {
+                write!(f, "render pass name '{s}' is not unique")
+            }
+            ConfigError::NumericRenderPass(s) => {
+                write!(
+                    f,
+                    "render pass name '{s}' is a number, which refers to an index"
+                )
+            }
             ConfigError::UnknownImage(i) => write!(f, "image '{i}' could not be found"),
+            ConfigError::InvalidImage(i, reason) => write!(f, "image '{i}' {reason}"),
             ConfigError::UnknownDisplay(d) => write!(f, "display '{d}' could not be found"),
             ConfigError::UnknownGroup(g) => write!(f, "group '{g}' could not be found"),
             ConfigError::NoRenderPasses => write!(f, "no render passes could be found"),
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Load a config file with the given contents from the temporary directory
+    pub fn load(contents: &str) -> Result<Config, ConfigError> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "wanipaper-test-{}-{}.config",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    const DISPLAY: &str = r##"
+        [displays.primary]
+        name = "DP-1"
+
+        [images.red]
+        color = "#ff0000"
+    "##;
+
+    fn load_named(name: &str) -> Result<Config, ConfigError> {
+        load(&format!(
+            r#"{DISPLAY}
+            [[renderpass]]
+            name = "{name}"
+            source = "red"
+            target = "primary"
+            "#
+        ))
+    }
+
+    #[test]
+    fn colors_parse() {
+        assert_eq!(parse_color("#1e1e2e"), Some([0x1e, 0x1e, 0x2e, 255]));
+        assert_eq!(parse_color("#FFFFFF80"), Some([255, 255, 255, 0x80]));
+
+        for color in [
+            "1e1e2e", "#1e1e2", "#1e1e2e8", "#1e1e2g", "#+f0000", "#ééé", "",
+        ] {
+            assert_eq!(parse_color(color), None, "{color}");
+        }
+    }
+
+    #[test]
+    fn backgrounds_parse() {
+        let parse = |s: &str| Background::try_from(s.to_string());
+        assert_eq!(parse("blur"), Ok(Background::Blur));
+        assert_eq!(parse("transparent"), Ok(Background::Transparent));
+        assert_eq!(parse("#00000080"), Ok(Background::Color([0, 0, 0, 0x80])));
+        assert!(parse("Blur").is_err());
+        assert!(parse("black").is_err());
+    }
+
+    #[test]
+    fn numeric_render_pass_names_are_rejected() {
+        for name in ["2", "0", "+1", "007"] {
+            assert!(matches!(
+                load_named(name),
+                Err(ConfigError::NumericRenderPass(n)) if n == name
+            ));
+        }
+
+        for name in ["desk", "2a", "-1", "1.5"] {
+            let config = load_named(name).unwrap();
+            assert_eq!(config.render_passes[0].name.as_deref(), Some(name));
+        }
+    }
+
+    #[test]
+    fn literal_paths_with_wildcards_are_not_globs() {
+        let dir = std::env::temp_dir().join(format!("wanipaper-test-{}-walls", std::process::id()));
+        for sub in ["walls[1]", "walls1"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            RgbaImage::new(1, 1)
+                .save(dir.join(sub).join("a.png"))
+                .unwrap();
+        }
+
+        let source = dir.join("walls[1]").to_string_lossy().into_owned();
+        let config = load(&format!(
+            "{DISPLAY}\n[[renderpass]]\nsource = '{source}'\ntarget = 'primary'"
+        ));
+        let glob = load(&format!(
+            "{DISPLAY}\n[[renderpass]]\nsource = '{}'\ntarget = 'primary'",
+            dir.join("walls[0-9]/*.png").display()
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            config.unwrap().image_sets[&source],
+            [format!("{source}/a.png")]
+        );
+        let glob = glob.unwrap();
+        let images = glob.image_sets.values().next().unwrap();
+        assert_eq!(*images, [format!("{}/walls1/a.png", dir.display())]);
+    }
+
+    #[test]
+    fn unused_images_are_kept() {
+        let config = load(&format!(
+            "{DISPLAY}\n[images.blue]\ncolor = '#0000ff'\n\n[[renderpass]]\nsource = 'red'\ntarget = 'primary'"
+        ))
+        .unwrap();
+        assert!(config.images.contains_key("blue"));
+    }
+}
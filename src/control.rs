@@ -0,0 +1,155 @@
+use crate::{
+    config::{RenderSource, RenderTarget},
+    ipc::{DisplayStatus, IpcError, PassSelector, PassStatus, Request, Response, Status},
+    state::State,
+};
+use wayland_client::QueueHandle;
+
+impl State {
+    pub fn handle_request(&mut self, request: Request, qh: &QueueHandle<Self>) -> Response {
+        let result = match request {
+            Request::Next { pass } => self.request_rotate(pass, false),
+            Request::Prev { pass } => self.request_rotate(pass, true),
+            Request::Set { pass, image } => self.request_set(pass, image),
+            Request::Pause { pass } => self.request_pause(pass, true),
+            Request::Resume { pass } => self.request_pause(pass, false),
+            Request::Reload => self.reload(qh).map_err(|e| IpcError::Reload(e.to_string())),
+            Request::Status => return Response::Status(self.status()),
+        };
+
+        match result {
+            Ok(()) => {
+                self.draw(qh);
+                Response::Ok
+            }
+            Err(error) => Response::Error { error },
+        }
+    }
+
+    /// Index of a render pass by index or name
+    fn resolve_pass(&self, pass: &PassSelector) -> Result<usize, IpcError> {
+        match pass {
+            PassSelector::Index(index) if *index < self.config.render_passes.len() => Ok(*index),
+            PassSelector::Index(_) => Err(IpcError::UnknownPass(pass.clone())),
+            PassSelector::Name(name) => self
+                .config
+                .render_passes
+                .iter()
+                .position(|p| p.name.as_ref() == Some(name))
+                .ok_or_else(|| IpcError::UnknownPass(pass.clone())),
+        }
+    }
+
+    /// Render passes a request applies to, every rotating pass if unspecified
+    fn request_passes(&self, pass: Option<PassSelector>) -> Result<Vec<usize>, IpcError> {
+        match pass {
+            Some(pass) => {
+                let index = self.resolve_pass(&pass)?;
+                match self.config.render_passes[index].source {
+                    RenderSource::Single(_) => Err(IpcError::NotRotating(pass)),
+                    RenderSource::Many { .. } => Ok(vec![index]),
+                }
+            }
+            None => Ok(self
+                .config
+                .render_passes
+                .iter()
+                .enumerate()
+                .filter(|(_, pass)| matches!(pass.source, RenderSource::Many { .. }))
+                .map(|(index, _)| index)
+                .collect()),
+        }
+    }
+
+    fn request_rotate(
+        &mut self,
+        pass: Option<PassSelector>,
+        backwards: bool,
+    ) -> Result<(), IpcError> {
+        for index in self.request_passes(pass)? {
+            self.rotate(index, backwards);
+            self.restart_timer(index);
+        }
+        Ok(())
+    }
+
+    fn request_set(&mut self, pass: PassSelector, image: String) -> Result<(), IpcError> {
+        let index = self.resolve_pass(&pass)?;
+        let pass = &self.config.render_passes[index];
+
+        if !self.config.images.contains_key(&image) {
+            return Err(IpcError::UnknownImage(image));
+        }
+
+        let position = match &pass.source {
+            RenderSource::Many { images, .. } => images.iter().position(|i| *i == image),
+            RenderSource::Single(_) => None,
+        };
+
+        match position {
+            Some(position) => {
+                self.render_pass_rotate_index.insert(index, position);
+                self.render_pass_override.remove(&index);
+            }
+            None => {
+                self.render_pass_override.insert(index, image);
+            }
+        }
+
+        self.damage_target(&self.config.render_passes[index].target);
+        self.restart_timer(index);
+        Ok(())
+    }
+
+    fn request_pause(&mut self, pass: Option<PassSelector>, paused: bool) -> Result<(), IpcError> {
+        for index in self.request_passes(pass)? {
+            if paused {
+                self.render_pass_paused.insert(index);
+            } else {
+                self.render_pass_paused.remove(&index);
+            }
+            self.restart_timer(index);
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> Status {
+        let passes = self
+            .config
+            .render_passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                let (images, rotate) = match &pass.source {
+                    RenderSource::Single(image) => (vec![image.clone()], None),
+                    RenderSource::Many { images, rotate, .. } => (images.clone(), *rotate),
+                };
+
+                PassStatus {
+                    index,
+                    name: pass.name.clone(),
+                    target: match &pass.target {
+                        RenderTarget::Display(d) | RenderTarget::Group(d) => d.clone(),
+                    },
+                    image: self.pass_image(index).cloned().unwrap_or_default(),
+                    images,
+                    rotate,
+                    paused: self.render_pass_paused.contains(&index),
+                }
+            })
+            .collect();
+
+        let mut displays = self
+            .displays
+            .iter()
+            .map(|(ident, display)| DisplayStatus {
+                ident: ident.clone(),
+                position: (display.region.min.x, display.region.min.y),
+                size: (display.region.dim.x, display.region.dim.y),
+            })
+            .collect::<Vec<_>>();
+        displays.sort_by(|a, b| a.ident.cmp(&b.ident));
+
+        Status { passes, displays }
+    }
+}
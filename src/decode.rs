@@ -0,0 +1,256 @@
+//! Decoding images on demand
+//!
+//! Loading the config only reads image headers. Pixels are decoded when a
+//! render pass first needs them and kept within a memory budget, evicting the
+//! least recently used. The next image of a rotation is decoded in the
+//! background ahead of time, so rotating stays instant.
+//!
+//! Images are reduced after decoding to the largest size any render pass
+//! draws them at, and only decoded again if a larger size is needed.
+
+use crate::{
+    config::{ConfigError, ImageSource, LoadedImage},
+    mq::{Message, MqSender},
+    worker::WorkerPool,
+};
+use image::{
+    imageops::{self, FilterType},
+    ImageReader, RgbaImage,
+};
+use log::info;
+use std::{
+    collections::{HashMap, HashSet},
+    panic,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// Memory kept for decoded images without a `[cache]` setting
+pub const DEFAULT_MEMORY_BUDGET: u64 = 512 * 1024 * 1024;
+
+/// Identifies a decoded file, so edited files are decoded again
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecodeKey {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+}
+
+/// Decoded images, least recently used are dropped once over budget
+pub struct ImageCache {
+    budget: u64,
+    images: HashMap<DecodeKey, Cached>,
+    clock: u64,
+    /// Being prefetched by a worker
+    pending: HashSet<DecodeKey>,
+}
+
+struct Cached {
+    image: Arc<RgbaImage>,
+    /// Fraction of the original size
+    scale: f64,
+    last_used: u64,
+}
+
+impl LoadedImage {
+    /// Cache key of an image decoded from a file
+    pub fn decode_key(&self) -> Option<DecodeKey> {
+        match &self.source {
+            ImageSource::File { path, modified, .. } => Some(DecodeKey {
+                path: path.clone(),
+                modified: *modified,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl ImageCache {
+    pub fn new(budget: u64) -> ImageCache {
+        ImageCache {
+            budget,
+            images: HashMap::new(),
+            clock: 0,
+            pending: HashSet::new(),
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+        self.evict(None);
+    }
+
+    /// Pixels of a file at no less than `scale` of its size, if cached
+    pub fn get(&mut self, key: &DecodeKey, scale: f64) -> Option<Arc<RgbaImage>> {
+        let scale = scale.min(1.0);
+
+        self.clock += 1;
+        let cached = self.images.get_mut(key).filter(|c| c.scale >= scale)?;
+        cached.last_used = self.clock;
+        Some(cached.image.clone())
+    }
+
+    /// Decode a file on a worker, sending [`Message::Decoded`] when done
+    pub fn prefetch(
+        &mut self,
+        key: &DecodeKey,
+        scale: f64,
+        filter: FilterType,
+        workers: &WorkerPool,
+        send: &MqSender<Message>,
+    ) {
+        let scale = scale.min(1.0);
+        if self.covers(key, scale) || !self.pending.insert(key.clone()) {
+            return;
+        }
+
+        let key = key.clone();
+        let send = send.clone();
+        workers.spawn(move || {
+            let image = panic::catch_unwind(|| decode(&key.path, scale, filter))
+                .ok()
+                .and_then(Result::ok)
+                .map(Arc::new);
+            let _ = send.send(Message::Decoded(key, scale, image));
+        });
+    }
+
+    /// Store the result of a decode on a worker
+    pub fn finish(&mut self, key: DecodeKey, scale: f64, image: Option<Arc<RgbaImage>>) {
+        self.pending.remove(&key);
+        if let Some(image) = image
+            && !self.covers(&key, scale)
+        {
+            self.insert(key, image, scale);
+        }
+    }
+
+    /// Whether a cached image is at least `scale` of its size
+    fn covers(&self, key: &DecodeKey, scale: f64) -> bool {
+        self.images.get(key).is_some_and(|c| c.scale >= scale)
+    }
+
+    fn insert(&mut self, key: DecodeKey, image: Arc<RgbaImage>, scale: f64) {
+        self.clock += 1;
+        let cached = Cached {
+            image,
+            scale,
+            last_used: self.clock,
+        };
+        self.images.insert(key.clone(), cached);
+        self.evict(Some(&key));
+    }
+
+    /// Drop least recently used images until within budget, always keeping `keep`
+    fn evict(&mut self, keep: Option<&DecodeKey>) {
+        let size = |cached: &Cached| cached.image.as_raw().len() as u64;
+        let mut total = self.images.values().map(size).sum::<u64>();
+
+        while total > self.budget {
+            let oldest = self
+                .images
+                .iter()
+                .filter(|(key, _)| Some(*key) != keep)
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(key, _)| key.clone());
+            let Some(cached) = oldest.and_then(|key| self.images.remove(&key)) else {
+                return;
+            };
+            total -= size(&cached);
+        }
+    }
+}
+
+/// Read an image file, reduced to `scale` of its size if below 1
+pub fn decode(path: &Path, scale: f64, filter: FilterType) -> Result<RgbaImage, ConfigError> {
+    info!("decode image {:?}", path);
+
+    let image = ImageReader::open(path)
+        .map_err(ConfigError::Io)?
+        .with_guessed_format()
+        .map_err(ConfigError::Io)?
+        .decode()
+        .map_err(ConfigError::Image)?
+        .into_rgba8();
+
+    if scale >= 1.0 {
+        return Ok(image);
+    }
+
+    // Rounded up, so the image is never smaller than needed
+    let (width, height) = image.dimensions();
+    let reduce = |i: u32| ((f64::from(i) * scale).ceil() as u32).max(1);
+    Ok(imageops::resize(
+        &image,
+        reduce(width),
+        reduce(height),
+        filter,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> DecodeKey {
+        DecodeKey {
+            path: PathBuf::from(name),
+            modified: None,
+        }
+    }
+
+    /// Image of `bytes` bytes, four per pixel
+    fn image(bytes: u32) -> Option<Arc<RgbaImage>> {
+        Some(Arc::new(RgbaImage::new(bytes / 4, 1)))
+    }
+
+    #[test]
+    fn least_recently_used_are_evicted_over_budget() {
+        let mut cache = ImageCache::new(300);
+        cache.finish(key("a"), 1.0, image(100));
+        cache.finish(key("b"), 1.0, image(100));
+        cache.finish(key("c"), 1.0, image(100));
+
+        // Using a makes b the least recently used
+        assert!(cache.get(&key("a"), 1.0).is_some());
+        cache.finish(key("d"), 1.0, image(100));
+
+        assert!(cache.get(&key("b"), 1.0).is_none());
+        for name in ["a", "c", "d"] {
+            assert!(cache.get(&key(name), 1.0).is_some(), "{name}");
+        }
+
+        // Lowering the budget evicts down to it
+        cache.set_budget(100);
+        assert!(cache.get(&key("d"), 1.0).is_some());
+        assert!(cache.get(&key("a"), 1.0).is_none());
+        assert!(cache.get(&key("c"), 1.0).is_none());
+    }
+
+    #[test]
+    fn newest_image_is_kept_over_budget() {
+        let mut cache = ImageCache::new(100);
+        cache.finish(key("a"), 1.0, image(80));
+        cache.finish(key("large"), 1.0, image(400));
+
+        assert!(cache.get(&key("large"), 1.0).is_some());
+        assert!(cache.get(&key("a"), 1.0).is_none());
+    }
+
+    #[test]
+    fn smaller_decodes_miss_larger_scales() {
+        let mut cache = ImageCache::new(1000);
+        cache.finish(key("a"), 0.5, image(100));
+
+        assert!(cache.get(&key("a"), 0.25).is_some());
+        assert!(cache.get(&key("a"), 0.5).is_some());
+        assert!(cache.get(&key("a"), 0.75).is_none());
+        // Scales above the full size are capped to it
+        cache.finish(key("a"), 1.0, image(400));
+        assert!(cache.get(&key("a"), 2.0).is_some());
+
+        // A smaller decode finishing later doesn't replace a larger one
+        cache.finish(key("a"), 0.5, image(100));
+        assert_eq!(cache.get(&key("a"), 1.0).unwrap().width(), 100);
+    }
+}
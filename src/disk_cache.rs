@@ -0,0 +1,393 @@
+//! Resized images kept on disk between runs
+//!
+//! Resizes of image files are written to `$XDG_CACHE_HOME/wani` as
+//! premultiplied BGRA, the format of `wl_shm` ARGB8888 buffers, so a cached
+//! wallpaper can be mapped and copied straight into a display's buffer
+//! without decoding.
+//! Files are named by a hash of the image file's path, modification time and
+//! size, and the resize drawn. The least recently used are removed once the
+//! files written since the last trim take the cache over budget.
+
+use crate::{
+    config::LoadedImage,
+    display::{argb8888, from_argb8888},
+    resize::ResizeKey,
+    xdg,
+};
+use cgmath::Vector2;
+use image::RgbaImage;
+use log::{debug, warn};
+use nix::sys::mman::{self, MapFlags, ProtFlags};
+use std::{
+    ffi::c_void,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    num::NonZeroUsize,
+    path::PathBuf,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// Disk space kept for resized images without a `[cache]` setting
+pub const DEFAULT_DISK_BUDGET: u64 = 1024 * 1024 * 1024;
+
+const MAGIC: &[u8; 4] = b"WANI";
+/// Bumped when the file layout changes, so old files are never read
+const VERSION: u32 = 2;
+/// Magic, version, width and height
+const HEADER_LEN: usize = 16;
+
+#[derive(Clone)]
+pub struct DiskCache {
+    /// `None` if there is no cache directory or the cache is disabled
+    dir: Option<PathBuf>,
+    budget: u64,
+    /// Size of the cache when last trimmed plus the bytes written since,
+    /// `None` until first trimmed
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+/// Read-only mapping of a cached resize
+pub struct Mapped {
+    ptr: NonNull<c_void>,
+    len: usize,
+    pub dim: Vector2<u32>,
+}
+
+impl DiskCache {
+    pub fn new(budget: u64) -> DiskCache {
+        DiskCache::in_dir(xdg::cache_home().map(|dir| dir.join("wani")), budget)
+    }
+
+    /// Cache in the given directory, disabled if `None` or without a budget
+    pub fn in_dir(dir: Option<PathBuf>, budget: u64) -> DiskCache {
+        DiskCache {
+            dir: dir.filter(|_| budget > 0),
+            budget,
+            size: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Map the cached resize of an image file, if one of the given size exists
+    pub fn load(&self, image: &LoadedImage, key: &ResizeKey, dim: Vector2<u32>) -> Option<Mapped> {
+        let path = self.path(image, key, dim)?;
+        let file = File::open(&path).ok()?;
+        let mapped = Mapped::new(&file)?;
+        if mapped.dim != dim {
+            return None;
+        }
+
+        // Modification time orders files by last use
+        let _ = File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()));
+
+        debug!("loaded cached resize {:?}", path);
+        Some(mapped)
+    }
+
+    /// Write the resize of an image file, trimming the cache once over budget
+    pub fn store(&self, image: &LoadedImage, key: &ResizeKey, resized: &RgbaImage) {
+        let dim = Vector2::from(resized.dimensions());
+        let Some(path) = self.path(image, key, dim) else {
+            return;
+        };
+
+        let mut data = Vec::with_capacity(HEADER_LEN + resized.as_raw().len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&dim.x.to_le_bytes());
+        data.extend_from_slice(&dim.y.to_le_bytes());
+        for pixel in resized.pixels() {
+            data.extend_from_slice(&argb8888(*pixel));
+        }
+
+        // Written aside and renamed, so a partial file is never mapped and
+        // existing mappings keep the replaced file
+        let partial = path.with_extension("partial");
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| File::create(&partial))
+            .and_then(|mut file| file.write_all(&data))
+            .and_then(|_| std::fs::rename(&partial, &path));
+        if let Err(e) = written {
+            warn!("failed to cache resize {:?}: {e}", path);
+            let _ = std::fs::remove_file(&partial);
+            return;
+        }
+
+        // Workers storing at once trim one at a time, and only when needed
+        let mut size = self.size.lock().unwrap();
+        match *size {
+            Some(total) if total + data.len() as u64 <= self.budget => {
+                *size = Some(total + data.len() as u64);
+            }
+            _ => *size = self.trim(),
+        }
+    }
+
+    fn path(&self, image: &LoadedImage, key: &ResizeKey, dim: Vector2<u32>) -> Option<PathBuf> {
+        let decode_key = image.decode_key()?;
+        let size = std::fs::metadata(&decode_key.path).ok()?.len();
+
+        // The standard hasher is stable within a build, a new build only
+        // misses the existing files
+        let mut hasher = DefaultHasher::new();
+        VERSION.hash(&mut hasher);
+        decode_key.hash(&mut hasher);
+        size.hash(&mut hasher);
+        key.resize.hash(&mut hasher);
+        image
+            .focus
+            .map(|f| (f.x.to_bits(), f.y.to_bits()))
+            .hash(&mut hasher);
+        (dim.x, dim.y).hash(&mut hasher);
+
+        Some(
+            self.dir
+                .as_ref()?
+                .join(format!("{:016x}.bgra", hasher.finish())),
+        )
+    }
+
+    /// Remove the least recently used files until within budget, returning the
+    /// size left
+    ///
+    /// Files still being written by other workers are left alone.
+    fn trim(&self) -> Option<u64> {
+        let entries = std::fs::read_dir(self.dir.as_ref()?).ok()?;
+
+        let mut files = entries
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "bgra"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let modified = metadata.modified().ok()?;
+                Some((modified, metadata.len(), entry.path()))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>();
+        for (_, len, path) in files {
+            if total <= self.budget {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+        Some(total)
+    }
+}
+
+impl Mapped {
+    fn new(file: &File) -> Option<Mapped> {
+        let len = usize::try_from(file.metadata().ok()?.len()).ok()?;
+        let ptr = unsafe {
+            mman::mmap(
+                None,
+                NonZeroUsize::new(len)?,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                file,
+                0,
+            )
+        }
+        .ok()?;
+
+        let mut mapped = Mapped {
+            ptr,
+            len,
+            dim: Vector2::new(0, 0),
+        };
+
+        let header = mapped.bytes().get(..HEADER_LEN)?;
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        if &header[..4] != MAGIC || word(4) != VERSION {
+            return None;
+        }
+        let dim = Vector2::new(word(8), word(12));
+        let expected = (dim.x as usize)
+            .checked_mul(dim.y as usize)?
+            .checked_mul(4)?
+            .checked_add(HEADER_LEN)?;
+        if len != expected {
+            return None;
+        }
+
+        mapped.dim = dim;
+        Some(mapped)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // Mapped read-only for `len` bytes until dropped
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+    }
+
+    /// Premultiplied pixels in BGRA order
+    pub fn pixels(&self) -> &[u8] {
+        &self.bytes()[HEADER_LEN..]
+    }
+
+    pub fn to_image(&self) -> RgbaImage {
+        let rgba = self
+            .pixels()
+            .chunks_exact(4)
+            .flat_map(|argb| from_argb8888(argb.try_into().unwrap()).0)
+            .collect();
+        RgbaImage::from_raw(self.dim.x, self.dim.y, rgba).unwrap()
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        let _ = unsafe { mman::munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Anchor, Background, ImageSource, Resize, ResizeKind},
+        region::Region,
+    };
+    use image::Rgba;
+    use std::{path::Path, time::Duration};
+
+    /// Empty directory for a test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir =
+                std::env::temp_dir().join(format!("wanipaper-test-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn image(dir: &Path) -> (LoadedImage, ResizeKey) {
+        let path = dir.join("source.png");
+        std::fs::write(&path, b"source").unwrap();
+        let image = LoadedImage {
+            source: ImageSource::File {
+                path,
+                modified: None,
+                dim: Vector2::new(2, 1),
+            },
+            focus: None,
+        };
+        let key = ResizeKey {
+            image: "source".to_string(),
+            region: Region::new(Vector2::new(0, 0), Vector2::new(2, 1)),
+            scale: 120,
+            resize: Resize {
+                kind: ResizeKind::Cover,
+                filter: Default::default(),
+                anchor: Anchor::Center,
+                background: Background::Transparent,
+                tile_scale: 1,
+            },
+        };
+        (image, key)
+    }
+
+    /// Map a file with the given contents
+    fn map(dir: &Path, contents: &[u8]) -> Option<Mapped> {
+        let path = dir.join("mapped.bgra");
+        std::fs::write(&path, contents).unwrap();
+        Mapped::new(&File::open(path).unwrap())
+    }
+
+    fn header(magic: &[u8; 4], version: u32, dim: Vector2<u32>) -> Vec<u8> {
+        let mut header = magic.to_vec();
+        for word in [version, dim.x, dim.y] {
+            header.extend_from_slice(&word.to_le_bytes());
+        }
+        header
+    }
+
+    #[test]
+    fn stored_resizes_load_premultiplied() {
+        let dir = TempDir::new("cache-round-trip");
+        let cache = DiskCache::in_dir(Some(dir.0.join("cache")), 1024);
+        let (image, key) = image(&dir.0);
+
+        let pixels = [Rgba([255, 128, 0, 255]), Rgba([255, 128, 0, 128])];
+        let resized = RgbaImage::from_fn(2, 1, |x, _| pixels[x as usize]);
+        cache.store(&image, &key, &resized);
+
+        let mapped = cache.load(&image, &key, Vector2::new(2, 1)).unwrap();
+        assert_eq!(mapped.dim, Vector2::new(2, 1));
+        assert_eq!(mapped.pixels(), [0, 128, 255, 255, 0, 64, 128, 128]);
+        assert_eq!(mapped.to_image(), resized);
+
+        // Other sizes are separate files
+        assert!(cache.load(&image, &key, Vector2::new(1, 1)).is_none());
+    }
+
+    #[test]
+    fn invalid_files_are_not_mapped() {
+        let dir = TempDir::new("cache-invalid");
+        let dim = Vector2::new(2, 1);
+        let pixels = [0; 8];
+
+        let valid = [header(MAGIC, VERSION, dim), pixels.to_vec()].concat();
+        assert!(map(&dir.0, &valid).is_some());
+
+        let magic = [header(b"WAND", VERSION, dim), pixels.to_vec()].concat();
+        assert!(map(&dir.0, &magic).is_none());
+
+        let version = [header(MAGIC, VERSION - 1, dim), pixels.to_vec()].concat();
+        assert!(map(&dir.0, &version).is_none());
+
+        let short = [header(MAGIC, VERSION, dim), pixels[..4].to_vec()].concat();
+        assert!(map(&dir.0, &short).is_none());
+        assert!(map(&dir.0, &valid[..HEADER_LEN - 1]).is_none());
+
+        // The expected length of this header overflows
+        let overflow = header(MAGIC, VERSION, Vector2::new(u32::MAX, u32::MAX));
+        assert!(map(&dir.0, &overflow).is_none());
+    }
+
+    #[test]
+    fn trim_removes_oldest_files_and_skips_partial_files() {
+        let dir = TempDir::new("cache-trim");
+        let cache = DiskCache::in_dir(Some(dir.0.clone()), 250);
+
+        let now = SystemTime::now();
+        for (age, name) in [
+            (3, "a.bgra"),
+            (2, "b.bgra"),
+            (1, "c.bgra"),
+            (4, "d.partial"),
+        ] {
+            let path = dir.0.join(name);
+            std::fs::write(&path, [0; 100]).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(now - Duration::from_secs(age)))
+                .unwrap();
+        }
+
+        assert_eq!(cache.trim(), Some(200));
+        assert!(!dir.0.join("a.bgra").exists());
+        for name in ["b.bgra", "c.bgra", "d.partial"] {
+            assert!(dir.0.join(name).exists(), "{name}");
+        }
+    }
+}
@@ -1,5 +1,13 @@
-use crate::{region::Region, state::State};
-use image::RgbaImage;
+use crate::{
+    config::Bezel,
+    layout::Insets,
+    region::Region,
+    scale::{SurfaceScale, SCALE_DENOMINATOR},
+    single_pixel::SinglePixel,
+    state::State,
+};
+use cgmath::Vector2;
+use image::Rgba;
 use smithay_client_toolkit::{
     shell::{wlr_layer::LayerSurface, WaylandSurface},
     shm::multi::MultiPool,
@@ -8,64 +16,113 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use wayland_client::{protocol::wl_shm, QueueHandle};
+use wayland_client::{
+    protocol::{
+        wl_output::{Transform, WlOutput},
+        wl_shm,
+    },
+    QueueHandle,
+};
+
+const MM_PER_INCH: f64 = 25.4;
 
 pub struct Display {
+    /// Ident of the `[displays.*]` entry matching this output
+    pub entry: String,
+    pub output: WlOutput,
     pub layer: (LayerSurface, usize),
     pub pool: MultiPool<(LayerSurface, usize)>,
     pub first: bool,
     pub damaged: Arc<AtomicBool>,
     pub region: Region,
+    /// Integer scale factor of the output
+    pub scale: i32,
+    /// Fractional scale, if the compositor supports it
+    pub surface_scale: Option<SurfaceScale>,
+    pub transform: Transform,
+    /// Size in millimetres, zero if unknown
+    pub physical_size: Vector2<i32>,
+    pub bezel: Bezel,
+    /// Physical pixels per inch, overriding the physical size
+    pub dpi: Option<f64>,
 }
 
 impl Display {
-    pub fn draw(&mut self, qh: &QueueHandle<State>, image: &RgbaImage, total: Region) {
-        if self.first || !self.damaged.load(Ordering::Acquire) {
+    /// Scale factor in 120ths, preferring the fractional scale
+    pub fn scale(&self) -> u32 {
+        self.surface_scale
+            .as_ref()
+            .and_then(|s| s.preferred)
+            .unwrap_or(self.scale as u32 * SCALE_DENOMINATOR)
+    }
+
+    /// Logical pixels per millimetre, if the size of the display is known
+    pub fn density(&self) -> Option<f64> {
+        if let Some(dpi) = self.dpi {
+            let scale = f64::from(self.scale()) / f64::from(SCALE_DENOMINATOR);
+            return Some(dpi / MM_PER_INCH / scale);
+        }
+
+        (self.physical_size.x > 0)
+            .then(|| f64::from(self.region.dim.x) / f64::from(self.physical_size.x))
+    }
+
+    /// Bezel in logical pixels, empty if it is in millimetres and the size is unknown
+    pub fn bezel_insets(&self) -> Insets {
+        self.bezel.insets(self.density()).unwrap_or_default()
+    }
+
+    /// Size in physical pixels, before the output transform is applied
+    pub fn buffer_dim(&self) -> Vector2<u32> {
+        scale_dim(self.region.dim, self.scale())
+    }
+
+    /// Whether the display is waiting to be redrawn
+    pub fn needs_draw(&self) -> bool {
+        !self.first && self.damaged.load(Ordering::Acquire)
+    }
+
+    /// Present ARGB8888 pixels of [`Display::buffer_dim`] size, already in the
+    /// output's orientation
+    pub fn draw(&mut self, qh: &QueueHandle<State>, dim: Vector2<u32>, pixels: &[u8]) {
+        if !self.needs_draw() {
             return;
         }
 
+        self.present(qh, dim, |canvas| canvas.copy_from_slice(pixels));
+    }
+
+    /// Attach a buffer filled with ARGB8888 pixels by `fill`
+    fn present(&mut self, qh: &QueueHandle<State>, dim: Vector2<u32>, fill: impl Fn(&mut [u8])) {
+        let (width, height) = (dim.x as i32, dim.y as i32);
+
         let layer = &self.layer.0;
         for i in 0..2 {
             self.layer.1 = i;
             let Ok((_offset, buffer, canvas)) = self.pool.create_buffer(
-                self.region.dim.x,
-                self.region.dim.x * 4,
-                self.region.dim.y,
+                width,
+                width * 4,
+                height,
                 &self.layer,
                 wl_shm::Format::Argb8888,
             ) else {
                 continue;
             };
 
-            if self.region == total {
-                for (pixel, argb) in image.pixels().zip(canvas.chunks_exact_mut(4)) {
-                    argb[3] = pixel.0[3];
-                    argb[2] = pixel.0[0];
-                    argb[1] = pixel.0[1];
-                    argb[0] = pixel.0[2];
-                }
-            } else {
-                let image = image::imageops::crop_imm(
-                    image,
-                    (self.region.min.x - total.min.x) as u32,
-                    (self.region.min.y - total.min.y) as u32,
-                    self.region.dim.x as u32,
-                    self.region.dim.y as u32,
-                )
-                .to_image();
-
-                for (pixel, argb) in image.pixels().zip(canvas.chunks_exact_mut(4)) {
-                    argb[3] = pixel.0[3];
-                    argb[2] = pixel.0[0];
-                    argb[1] = pixel.0[1];
-                    argb[0] = pixel.0[2];
+            fill(canvas);
+
+            layer.wl_surface().set_buffer_transform(self.transform);
+            match &self.surface_scale {
+                Some(surface_scale) => {
+                    layer.wl_surface().set_buffer_scale(1);
+                    surface_scale
+                        .viewport
+                        .set_destination(self.region.dim.x, self.region.dim.y);
                 }
+                None => layer.wl_surface().set_buffer_scale(self.scale),
             }
-
             // Damage the entire window
-            layer
-                .wl_surface()
-                .damage_buffer(0, 0, self.region.dim.x, self.region.dim.y);
+            layer.wl_surface().damage_buffer(0, 0, width, height);
             // Request our next frame
             layer
                 .wl_surface()
@@ -78,4 +135,97 @@ impl Display {
             return;
         }
     }
+
+    /// Fill the surface with a colour
+    ///
+    /// The colour is stretched from a single pixel buffer when the protocol
+    /// and the viewport of [`Display::surface_scale`] are available, otherwise
+    /// it is written straight to a buffer of the output's size.
+    pub fn draw_color(
+        &mut self,
+        qh: &QueueHandle<State>,
+        single_pixel: Option<&SinglePixel>,
+        color: [u8; 4],
+    ) {
+        if !self.needs_draw() {
+            return;
+        }
+        let (Some(single_pixel), Some(surface_scale)) = (single_pixel, &self.surface_scale) else {
+            let mut dim = self.buffer_dim();
+            if swaps_axes(self.transform) {
+                dim = Vector2::new(dim.y, dim.x);
+            }
+            let argb = argb8888(Rgba(color));
+            self.present(qh, dim, |canvas| {
+                for pixel in canvas.chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&argb);
+                }
+            });
+            return;
+        };
+
+        let layer = &self.layer.0;
+        layer.wl_surface().set_buffer_transform(Transform::Normal);
+        layer.wl_surface().set_buffer_scale(1);
+        surface_scale
+            .viewport
+            .set_destination(self.region.dim.x, self.region.dim.y);
+        layer.wl_surface().damage_buffer(0, 0, 1, 1);
+        layer.wl_surface().frame(qh, layer.wl_surface().clone());
+        layer.attach(Some(&single_pixel.create(color, qh)), 0, 0);
+        layer.commit();
+
+        self.damaged.store(false, Ordering::Release);
+    }
+}
+
+/// Logical dimensions in physical pixels at a scale in 120ths
+pub fn scale_dim(dim: Vector2<i32>, scale: u32) -> Vector2<u32> {
+    let scale = f64::from(scale) / f64::from(SCALE_DENOMINATOR);
+    dim.map(|i| (f64::from(i.max(0)) * scale).round() as u32)
+}
+
+/// Whether a transform swaps the width and height of the output
+pub fn swaps_axes(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// Bytes of a pixel in `wl_shm` ARGB8888, which is little endian and premultiplied
+pub fn argb8888(pixel: Rgba<u8>) -> [u8; 4] {
+    let [r, g, b, a] = pixel.0;
+    let premultiply = |c: u8| ((u16::from(c) * u16::from(a) + 127) / 255) as u8;
+    [premultiply(b), premultiply(g), premultiply(r), a]
+}
+
+/// Straight alpha pixel of `wl_shm` ARGB8888 bytes
+pub fn from_argb8888(argb: [u8; 4]) -> Rgba<u8> {
+    let [b, g, r, a] = argb;
+    let unpremultiply = |c: u8| match a {
+        0 => 0,
+        a => ((u16::from(c) * 255 + u16::from(a) / 2) / u16::from(a)).min(255) as u8,
+    };
+    Rgba([unpremultiply(r), unpremultiply(g), unpremultiply(b), a])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argb8888_is_premultiplied() {
+        assert_eq!(argb8888(Rgba([255, 128, 0, 255])), [0, 128, 255, 255]);
+        assert_eq!(argb8888(Rgba([255, 128, 0, 128])), [0, 64, 128, 128]);
+        assert_eq!(argb8888(Rgba([255, 255, 255, 0])), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn argb8888_round_trips() {
+        for pixel in [[255, 128, 0, 255], [10, 20, 30, 255], [255, 128, 0, 128]] {
+            assert_eq!(from_argb8888(argb8888(Rgba(pixel))), Rgba(pixel));
+        }
+        assert_eq!(from_argb8888([0, 0, 0, 0]), Rgba([0, 0, 0, 0]));
+    }
 }
@@ -0,0 +1,153 @@
+//! Generated gradient images
+//!
+//! Gradients are drawn directly at the size of the target, following CSS:
+//! a linear gradient at 0 degrees runs bottom to top and turns clockwise,
+//! a radial gradient runs from the centre out to the furthest corner.
+//! Stops are interpolated with premultiplied alpha.
+
+use cgmath::Vector2;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientKind {
+    #[default]
+    Linear,
+    Radial,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    /// Direction of a linear gradient in degrees
+    pub angle: f64,
+    /// Sorted by position
+    pub stops: Vec<ColorStop>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorStop {
+    pub color: [u8; 4],
+    /// Position along the gradient, from 0 to 1
+    pub position: f64,
+}
+
+impl Gradient {
+    pub fn render(&self, dim: Vector2<u32>) -> RgbaImage {
+        let size = dim.map(f64::from);
+        let center = size / 2.0;
+
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let direction = Vector2::new(sin, -cos);
+        // Long enough for the corners to reach the first and last stop
+        let length = (size.x * sin).abs() + (size.y * cos).abs();
+        let radius = center.x.hypot(center.y);
+
+        RgbaImage::from_fn(dim.x, dim.y, |x, y| {
+            let offset = Vector2::new(f64::from(x) + 0.5, f64::from(y) + 0.5) - center;
+            let t = match self.kind {
+                GradientKind::Linear if length > 0.0 => {
+                    (offset.x * direction.x + offset.y * direction.y) / length + 0.5
+                }
+                GradientKind::Radial if radius > 0.0 => offset.x.hypot(offset.y) / radius,
+                _ => 0.0,
+            };
+            Rgba(self.color_at(t))
+        })
+    }
+
+    fn color_at(&self, t: f64) -> [u8; 4] {
+        let next = self.stops.iter().position(|s| s.position >= t);
+        let (from, to) = match next {
+            Some(0) => return self.stops[0].color,
+            Some(i) => (self.stops[i - 1], self.stops[i]),
+            None => return self.stops.last().map_or([0; 4], |s| s.color),
+        };
+
+        let width = to.position - from.position;
+        let f = if width > 0.0 {
+            (t - from.position) / width
+        } else {
+            1.0
+        };
+
+        let alpha = |c: [u8; 4]| f64::from(c[3]) / 255.0;
+        let (from_alpha, to_alpha) = (alpha(from.color), alpha(to.color));
+        let out_alpha = from_alpha + (to_alpha - from_alpha) * f;
+
+        let mut color = [0; 4];
+        for (channel, out) in color.iter_mut().take(3).enumerate() {
+            let from = f64::from(from.color[channel]) * from_alpha;
+            let to = f64::from(to.color[channel]) * to_alpha;
+            let premultiplied = from + (to - from) * f;
+            *out = if out_alpha > 0.0 {
+                (premultiplied / out_alpha).round() as u8
+            } else {
+                0
+            };
+        }
+        color[3] = (out_alpha * 255.0).round() as u8;
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(stops: &[([u8; 4], f64)]) -> Gradient {
+        Gradient {
+            kind: GradientKind::Linear,
+            angle: 90.0,
+            stops: stops
+                .iter()
+                .map(|&(color, position)| ColorStop { color, position })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn colors_are_clamped_outside_the_stops() {
+        let gradient = gradient(&[([255, 0, 0, 255], 0.25), ([0, 0, 255, 255], 0.75)]);
+        assert_eq!(gradient.color_at(0.0), [255, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.25), [255, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.75), [0, 0, 255, 255]);
+        assert_eq!(gradient.color_at(1.0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn colors_are_interpolated_between_stops() {
+        let gradient = gradient(&[([255, 0, 0, 255], 0.0), ([0, 0, 255, 255], 1.0)]);
+        assert_eq!(gradient.color_at(0.5), [128, 0, 128, 255]);
+        assert_eq!(gradient.color_at(0.25), [191, 0, 64, 255]);
+    }
+
+    #[test]
+    fn transparent_stops_keep_the_other_colour() {
+        let gradient = gradient(&[([255, 0, 0, 255], 0.0), ([0, 0, 255, 0], 1.0)]);
+        assert_eq!(gradient.color_at(0.5), [255, 0, 0, 128]);
+        assert_eq!(gradient.color_at(1.0)[3], 0);
+    }
+
+    #[test]
+    fn coincident_stops_make_a_hard_edge() {
+        let gradient = gradient(&[
+            ([255, 0, 0, 255], 0.0),
+            ([255, 0, 0, 255], 0.5),
+            ([0, 0, 255, 255], 0.5),
+            ([0, 0, 255, 255], 1.0),
+        ]);
+        assert_eq!(gradient.color_at(0.49), [255, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.5), [255, 0, 0, 255]);
+        assert_eq!(gradient.color_at(0.51), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn linear_gradients_follow_the_angle() {
+        let gradient = gradient(&[([0, 0, 0, 255], 0.0), ([255, 255, 255, 255], 1.0)]);
+        let image = gradient.render(Vector2::new(4, 1));
+        let reds = image.pixels().map(|p| p.0[0]).collect::<Vec<_>>();
+        assert_eq!(reds, [32, 96, 159, 223]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Control socket protocol
+//!
+//! Clients send a single JSON [`Request`] terminated by a newline and receive
+//! a single JSON [`Response`] line before the connection is closed.
+//!
+//! Each connection is read on its own thread, which passes the request to the
+//! event loop as a [`Message::Request`], so a stalled client never blocks it.
+
+use crate::{
+    mq::{Message, MqSender},
+    xdg,
+};
+use log::{error, info, warn};
+use nix::sys::epoll::{Epoll, EpollEvent, EpollFlags};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Advance a rotating render pass, or all of them
+    Next {
+        #[serde(default)]
+        pass: Option<PassSelector>,
+    },
+    /// Step a rotating render pass back, or all of them
+    Prev {
+        #[serde(default)]
+        pass: Option<PassSelector>,
+    },
+    /// Show an image on a render pass until it next rotates
+    Set { pass: PassSelector, image: String },
+    /// Stop rotation timers for a render pass, or all of them
+    Pause {
+        #[serde(default)]
+        pass: Option<PassSelector>,
+    },
+    /// Restart rotation timers for a render pass, or all of them
+    Resume {
+        #[serde(default)]
+        pass: Option<PassSelector>,
+    },
+    /// Reload the config from disk
+    Reload,
+    /// Report render passes and displays
+    Status,
+}
+
+/// Render pass referred to by index or by its `name`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PassSelector {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Error { error: IpcError },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub passes: Vec<PassStatus>,
+    pub displays: Vec<DisplayStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassStatus {
+    pub index: usize,
+    pub name: Option<String>,
+    pub target: String,
+    pub image: String,
+    pub images: Vec<String>,
+    pub rotate: Option<usize>,
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayStatus {
+    pub ident: String,
+    pub position: (i32, i32),
+    pub size: (i32, i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum IpcError {
+    /// Request could not be parsed
+    InvalidRequest(String),
+    /// Render pass could not be found
+    UnknownPass(PassSelector),
+    /// Image could not be found
+    UnknownImage(String),
+    /// Render pass has a single source
+    NotRotating(PassSelector),
+    /// Config failed to reload
+    Reload(String),
+}
+
+/// Control socket path, `$XDG_RUNTIME_DIR/wanipaper-$WAYLAND_DISPLAY.sock`
+pub fn socket_path() -> Option<PathBuf> {
+    let display = std::env::var_os("WAYLAND_DISPLAY")
+        .map(PathBuf::from)
+        .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "wayland-0".to_string());
+
+    Some(xdg::runtime_dir()?.join(format!("wanipaper-{display}.sock")))
+}
+
+/// Listening control socket, removed when dropped
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl IpcServer {
+    pub fn bind(epoll: &Epoll, queue_id: u64) -> Result<IpcServer, Box<dyn Error>> {
+        let path = socket_path().ok_or("$XDG_RUNTIME_DIR is not set")?;
+
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(format!("control socket {path:?} is already in use").into());
+            }
+            // Left behind by a previous instance
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        epoll.add(&listener, EpollEvent::new(EpollFlags::EPOLLIN, queue_id))?;
+
+        info!("control socket {path:?}");
+
+        Ok(IpcServer { listener, path })
+    }
+
+    /// Serve every pending connection on a thread of its own
+    pub fn accept(&self, send: &MqSender<Message>) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let send = send.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = serve(stream, &send) {
+                            warn!("control connection failed: {e}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("failed to accept control connection: {e}");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Send a request to a running daemon and wait for its response
+pub fn request(path: &Path, request: &Request) -> Result<Response, Box<dyn Error>> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn serve(stream: UnixStream, send: &MqSender<Message>) -> Result<(), Box<dyn Error>> {
+    // Stalled clients are dropped rather than keeping the thread
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            send.send(Message::Request(request, reply))
+                .map_err(|e| e.to_string())?;
+            response.recv()?
+        }
+        Err(e) => Response::Error {
+            error: IpcError::InvalidRequest(e.to_string()),
+        },
+    };
+
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    (&stream).write_all(response.as_bytes())?;
+
+    Ok(())
+}
+
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpcError::InvalidRequest(e) => write!(f, "invalid request: {e}"),
+            IpcError::UnknownPass(p) => write!(f, "render pass {p} could not be found"),
+            IpcError::UnknownImage(i) => write!(f, "image '{i}' could not be found"),
+            IpcError::NotRotating(p) => write!(f, "render pass {p} has a single source"),
+            IpcError::Reload(e) => write!(f, "failed to reload config: {e}"),
+        }
+    }
+}
+
+impl FromStr for PassSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => PassSelector::Index(index),
+            Err(_) => PassSelector::Name(s.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for PassSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PassSelector::Index(i) => write!(f, "{i}"),
+            PassSelector::Name(n) => write!(f, "'{n}'"),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Placing the displays of a group on a shared canvas
+//!
+//! Displays keep their compositor arrangement, but are pushed apart so the
+//! bezels and gaps between them take up space on the canvas. The strips
+//! hidden behind bezels are never drawn, so lines continue across displays.
+//! Displays may also take up more of the canvas than their logical size, so
+//! an image keeps the same physical size across displays of different density.
+//! Displays side by side then keep whichever of their edges or centres were
+//! aligned, so a bottom-aligned pair stays bottom-aligned on the canvas.
+
+use crate::{
+    config::{Bezel, BezelUnit},
+    region::Region,
+};
+use cgmath::Vector2;
+use std::ops::Range;
+
+/// Hidden border around a display, in logical pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Insets {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+/// Display as arranged by the compositor
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutInput {
+    pub region: Region,
+    /// Size on the canvas
+    pub size: Vector2<i32>,
+    /// Bezel on the canvas
+    pub bezel: Insets,
+}
+
+/// Displays sharing one image
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    /// Area covered by the image
+    pub region: Region,
+    /// Display keys and the part of the canvas each shows
+    pub displays: Vec<(String, Region)>,
+}
+
+impl Bezel {
+    /// Bezel in logical pixels of a display
+    ///
+    /// Millimetres are converted using the density of the display in logical
+    /// pixels per millimetre, returning `None` if it is unknown.
+    pub fn insets(&self, density: Option<f64>) -> Option<Insets> {
+        let density = match self.unit {
+            BezelUnit::Px => 1.0,
+            BezelUnit::Mm => density?,
+        };
+
+        Some(Insets {
+            left: (self.left * density).round() as i32,
+            right: (self.right * density).round() as i32,
+            top: (self.top * density).round() as i32,
+            bottom: (self.bottom * density).round() as i32,
+        })
+    }
+}
+
+impl Insets {
+    pub fn scale(self, factor: f64) -> Insets {
+        let scale = |i: i32| (f64::from(i) * factor).round() as i32;
+        Insets {
+            left: scale(self.left),
+            right: scale(self.right),
+            top: scale(self.top),
+            bottom: scale(self.bottom),
+        }
+    }
+}
+
+impl Canvas {
+    /// Lay out displays on a canvas, `None` if there are no displays
+    ///
+    /// Each display is swept left to right, then top to bottom, and placed
+    /// after the furthest neighbour before it plus both bezels and the gap.
+    /// Along the other axis it is aligned to the first neighbour beside it.
+    pub fn layout(keys: Vec<String>, displays: &[LayoutInput], gap: i32) -> Option<Canvas> {
+        let horizontal = displays
+            .iter()
+            .map(|d| Extent {
+                min: d.region.min.x,
+                max: d.region.max.x,
+                size: d.size.x,
+                across: d.region.min.y..d.region.max.y,
+                leading: d.bezel.left,
+                trailing: d.bezel.right,
+            })
+            .collect::<Vec<_>>();
+        let vertical = displays
+            .iter()
+            .map(|d| Extent {
+                min: d.region.min.y,
+                max: d.region.max.y,
+                size: d.size.y,
+                across: d.region.min.x..d.region.max.x,
+                leading: d.bezel.top,
+                trailing: d.bezel.bottom,
+            })
+            .collect::<Vec<_>>();
+
+        let displays = keys
+            .into_iter()
+            .zip(displays)
+            .zip(
+                sweep(&horizontal, gap)
+                    .into_iter()
+                    .zip(sweep(&vertical, gap)),
+            )
+            .map(|((key, display), (x, y))| {
+                let min = Vector2::new(x, y);
+                (key, Region::new(min, min + display.size))
+            })
+            .collect::<Vec<_>>();
+
+        let region = displays
+            .iter()
+            .map(|(_, region)| *region)
+            .reduce(Region::combine)?;
+
+        Some(Canvas { region, displays })
+    }
+}
+
+/// Span of a display along one axis
+struct Extent {
+    min: i32,
+    max: i32,
+    /// Size on the canvas
+    size: i32,
+    /// Span along the other axis
+    across: Range<i32>,
+    leading: i32,
+    trailing: i32,
+}
+
+/// Canvas start of each display along one axis
+fn sweep(extents: &[Extent], gap: i32) -> Vec<i32> {
+    let mut order = (0..extents.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| extents[*i].min);
+
+    let mut start = extents.iter().map(|e| e.min).collect::<Vec<_>>();
+    for (position, &i) in order.iter().enumerate() {
+        let extent = &extents[i];
+
+        let beside = order[..position]
+            .iter()
+            .find(|&&j| extents[j].max > extent.min);
+        if let Some(&j) = beside {
+            start[i] = extent.align(&extents[j], start[j]);
+        }
+
+        for &j in &order[..position] {
+            let before = &extents[j];
+
+            // Only displays side by side push each other apart
+            let overlaps =
+                before.across.start < extent.across.end && extent.across.start < before.across.end;
+            if before.max > extent.min || !overlaps {
+                continue;
+            }
+
+            let end = start[j] + before.size + before.trailing + extent.leading + gap;
+            start[i] = start[i].max(end);
+        }
+    }
+
+    start
+}
+
+impl Extent {
+    /// Canvas start keeping this extent's offset from one it overlaps
+    ///
+    /// The leading edges, centres or trailing edges closest in the compositor
+    /// layout are kept at that distance, scaled to this extent's canvas size.
+    fn align(&self, other: &Extent, other_start: i32) -> i32 {
+        let logical = self.max - self.min;
+        let factor = match logical > 0 {
+            true => f64::from(self.size) / f64::from(logical),
+            false => 1.0,
+        };
+
+        let (size, other_size) = (f64::from(self.size), f64::from(other.size));
+        let centre = |e: &Extent| f64::from(e.min + e.max) / 2.0;
+        let other_start = f64::from(other_start);
+
+        // Offset in the compositor layout, and the canvas start keeping it
+        let candidates = [
+            (f64::from(self.min - other.min), other_start),
+            (
+                centre(self) - centre(other),
+                other_start + (other_size - size) / 2.0,
+            ),
+            (
+                f64::from(self.max - other.max),
+                other_start + other_size - size,
+            ),
+        ];
+
+        let (offset, start) = candidates
+            .into_iter()
+            .min_by(|(a, _), (b, _)| a.abs().total_cmp(&b.abs()))
+            .unwrap();
+        (start + offset * factor).round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(x: i32, y: i32, width: i32, height: i32) -> LayoutInput {
+        let min = Vector2::new(x, y);
+        LayoutInput {
+            region: Region::new(min, min + Vector2::new(width, height)),
+            size: Vector2::new(width, height),
+            bezel: Insets::default(),
+        }
+    }
+
+    /// Canvas position of each display
+    fn layout(displays: &[LayoutInput], gap: i32) -> Vec<(i32, i32)> {
+        let keys = (0..displays.len()).map(|i| i.to_string()).collect();
+        Canvas::layout(keys, displays, gap)
+            .unwrap()
+            .displays
+            .into_iter()
+            .map(|(_, region)| (region.min.x, region.min.y))
+            .collect()
+    }
+
+    #[test]
+    fn no_displays_have_no_canvas() {
+        assert!(Canvas::layout(Vec::new(), &[], 10).is_none());
+    }
+
+    #[test]
+    fn bezels_and_gaps_push_displays_apart() {
+        let mut left = input(0, 0, 1920, 1080);
+        left.bezel.right = 10;
+        let mut right = input(1920, 0, 1920, 1080);
+        right.bezel.left = 12;
+
+        assert_eq!(layout(&[left, right], 0), [(0, 0), (1942, 0)]);
+        assert_eq!(layout(&[left, right], 5), [(0, 0), (1947, 0)]);
+
+        let canvas = Canvas::layout(vec!["a".into(), "b".into()], &[left, right], 5).unwrap();
+        assert_eq!(
+            canvas.region,
+            Region::new(Vector2::new(0, 0), Vector2::new(3867, 1080))
+        );
+    }
+
+    #[test]
+    fn grid_is_pushed_apart_along_both_axes() {
+        let mut displays = [
+            input(0, 0, 1920, 1080),
+            input(1920, 0, 1920, 1080),
+            input(0, 1080, 1920, 1080),
+            input(1920, 1080, 1920, 1080),
+        ];
+        for display in &mut displays {
+            display.bezel = Insets {
+                left: 5,
+                right: 5,
+                top: 5,
+                bottom: 5,
+            };
+        }
+
+        assert_eq!(
+            layout(&displays, 0),
+            [(0, 0), (1930, 0), (0, 1090), (1930, 1090)]
+        );
+    }
+
+    #[test]
+    fn displays_apart_keep_their_position() {
+        // Not side by side, so nothing pushes them
+        let displays = [input(0, 0, 1920, 1080), input(3000, 2000, 1920, 1080)];
+        assert_eq!(layout(&displays, 50), [(0, 0), (3000, 2000)]);
+    }
+
+    #[test]
+    fn overlapping_displays_stay_overlapped() {
+        let displays = [input(0, 0, 1920, 1080), input(0, 0, 1920, 1080)];
+        assert_eq!(layout(&displays, 10), [(0, 0), (0, 0)]);
+
+        let displays = [input(0, 0, 1920, 1080), input(960, 0, 1920, 1080)];
+        assert_eq!(layout(&displays, 10), [(0, 0), (960, 0)]);
+    }
+
+    #[test]
+    fn mixed_densities_keep_bottom_edges_aligned() {
+        // 27" 4K at scale 1.5 beside a denser reference is drawn as is, a 24"
+        // 1080p display is drawn larger to match its physical size
+        let large = input(0, 0, 2560, 1440);
+        let mut small = input(2560, 360, 1920, 1080);
+        small.size = Vector2::new(2275, 1280);
+
+        let canvas =
+            Canvas::layout(vec!["large".into(), "small".into()], &[large, small], 0).unwrap();
+        assert_eq!(canvas.displays[1].1.min, Vector2::new(2560, 160));
+        assert_eq!(canvas.displays[1].1.max.y, canvas.displays[0].1.max.y);
+        assert_eq!(canvas.region.dim, Vector2::new(4835, 1440));
+    }
+
+    #[test]
+    fn mixed_densities_keep_centres_and_offsets() {
+        // Laptop centred below a monitor, drawn at 1.5 times its logical size
+        let monitor = input(0, 0, 2560, 1440);
+        let mut laptop = input(640, 1440, 1280, 800);
+        laptop.size = Vector2::new(1920, 1200);
+        assert_eq!(layout(&[monitor, laptop], 0), [(0, 0), (320, 1440)]);
+
+        // Offsets not lining anything up are scaled by the display's density
+        let mut laptop = input(2560, 100, 1280, 800);
+        laptop.size = Vector2::new(1920, 1200);
+        assert_eq!(layout(&[monitor, laptop], 0), [(0, 0), (2560, 150)]);
+    }
+}
@@ -0,0 +1,24 @@
+pub mod blend;
+pub mod cli;
+pub mod composite;
+pub mod config;
+pub mod control;
+pub mod decode;
+pub mod disk_cache;
+pub mod display;
+pub mod gradient;
+pub mod ipc;
+pub mod layout;
+pub mod matcher;
+pub mod mq;
+pub mod outputs;
+pub mod region;
+pub mod resize;
+pub mod rotate;
+pub mod scale;
+pub mod scan;
+pub mod single_pixel;
+pub mod state;
+pub mod watch;
+pub mod worker;
+pub mod xdg;
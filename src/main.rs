@@ -1,49 +1,119 @@
-use crate::config::{Config, RenderSource, RenderTarget};
-use log::{error, info, LevelFilter};
-use mq::EventKind;
+use clap::Parser;
+use log::{error, info, warn};
 use nix::{errno::Errno, sys::epoll::*};
-use rand::random_range;
 use smithay_client_toolkit::{
     compositor::CompositorState, output::OutputState, registry::RegistryState, seat::SeatState,
     shell::wlr_layer::LayerShell, shm::Shm,
 };
-use state::State;
 use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
-    time::Duration,
+    collections::{HashMap, HashSet},
+    path::Path,
+    process::ExitCode,
+};
+use wanipaper::{
+    cli::{Cli, Command},
+    composite::PendingComposites,
+    config::Config,
+    decode::ImageCache,
+    disk_cache::DiskCache,
+    ipc::IpcServer,
+    mq::{self, EventKind, Message},
+    outputs,
+    scale::FractionalScale,
+    single_pixel::SinglePixel,
+    state::State,
+    watch::Watcher,
+    worker::WorkerPool,
 };
 use wayland_client::{globals::registry_queue_init, Connection};
 
-pub mod config;
-pub mod display;
-pub mod mq;
-pub mod region;
-pub mod state;
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-fn main() {
     env_logger::builder()
-        .filter_level(LevelFilter::Info)
+        .filter_level(cli.log_level)
         .format_target(false)
         .format_timestamp(None)
         .format_module_path(true)
         .init();
 
-    let config = match Config::load() {
+    if let Some(Command::PrintOutputs) = cli.command {
+        return match outputs::print_outputs() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("{e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let config_path = match cli.config.clone() {
+        Some(path) => path,
+        None => match Config::default_path() {
+            Ok(path) => path,
+            Err(e) => {
+                error!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    match cli.command {
+        Some(Command::Check) => check(&config_path),
+        _ => run(&config_path, cli.once),
+    }
+}
+
+/// Load the config without connecting to the compositor
+fn check(config_path: &Path) -> ExitCode {
+    match Config::load(config_path) {
+        Ok(config) => {
+            println!(
+                "config ok: {} images, {} displays, {} groups, {} render passes",
+                config.images.len(),
+                config.displays.len(),
+                config.groups.len(),
+                config.render_passes.len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(config_path: &Path, once: bool) -> ExitCode {
+    let config = match Config::load(config_path) {
         Ok(c) => c,
         Err(e) => {
             error!("{e}");
-            return;
+            return ExitCode::FAILURE;
         }
     };
 
     info!("config loaded");
 
     let epoll = Epoll::new(EpollCreateFlags::empty()).unwrap();
-    let (mq_send, mq_recv) = mq::new::<()>(&epoll, EventKind::Mq as u64).unwrap();
+    let (mq_send, mq_recv) = mq::new::<Message>(&epoll, EventKind::Mq as u64).unwrap();
+
+    let mut watcher = Watcher::new(&epoll, EventKind::Watch as u64).unwrap();
+    if !once {
+        watcher.watch(config.watch_paths(), config.watch_dirs());
+    }
+
+    let ipc_server = if once {
+        None
+    } else {
+        match IpcServer::bind(&epoll, EventKind::Ipc as u64) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                warn!("control socket disabled: {e}");
+                None
+            }
+        }
+    };
 
     // All Wayland apps start by connecting the compositor (server).
     let conn = Connection::connect_to_env().unwrap();
@@ -56,17 +126,18 @@ fn main() {
         CompositorState::bind(&globals, &qh).expect("wl_compositor is not available");
     let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell is not available");
     let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
+    let fractional_scale = FractionalScale::bind(&globals, &qh);
+    if fractional_scale.is_none() {
+        info!("fractional scaling unavailable, using integer scales");
+    }
+    let single_pixel = SinglePixel::bind(&globals, &qh);
 
     let registry_state = RegistryState::new(&globals);
     let seat_state = SeatState::new(&globals, &qh);
     let output_state = OutputState::new(&globals, &qh);
 
-    let display_ident_map = config
-        .displays
-        .iter()
-        .map(|(n, d)| (d.name.clone(), n.clone()))
-        .collect::<HashMap<_, _>>();
-
+    let image_cache = ImageCache::new(config.memory_budget);
+    let disk_cache = DiskCache::new(config.disk_budget);
     let mut state = State {
         config,
         registry_state,
@@ -74,59 +145,41 @@ fn main() {
         output_state,
         compositor_state,
         shm,
+        fractional_scale,
+        single_pixel,
         first_configure: true,
         exit: false,
+        once,
         pointer: None,
+        mq_send,
+        watcher,
         displays: HashMap::new(),
+        composites: PendingComposites::default(),
         render_pass_resizes: HashMap::new(),
+        render_pass_pending: HashSet::new(),
+        render_pass_failed: HashSet::new(),
+        workers: WorkerPool::new(),
+        image_cache,
+        disk_cache,
         render_pass_rotate_index: HashMap::new(),
+        render_pass_next: HashMap::new(),
+        render_pass_timers: HashMap::new(),
+        render_pass_paused: HashSet::new(),
+        render_pass_override: HashMap::new(),
         layer_shell,
-        display_ident_map,
     };
 
-    let mut threads = Vec::new();
-    for (index, pass) in state.config.render_passes.iter().enumerate() {
-        if let RenderSource::Many {
-            images,
-            rotate: Some(timing),
-            rand,
-            ..
-        } = &pass.source
-        {
-            let len = images.len();
-            let atomic = Arc::new(AtomicUsize::new(0));
-            let timing = *timing;
-            let rand = *rand;
-            let send = mq_send.clone();
-
-            let mut targets = Vec::new();
-            match &pass.target {
-                RenderTarget::Display(display) => {
-                    targets.push(state.displays.get(display).unwrap().damaged.clone());
-                }
-                RenderTarget::Group(group) => {
-                    for display in &state.config.groups.get(group).unwrap().displays {
-                        targets.push(state.displays.get(display).unwrap().damaged.clone());
-                    }
-                }
-            }
-
-            state.render_pass_rotate_index.insert(index, atomic.clone());
-
-            threads.push(std::thread::spawn(move || loop {
-                std::thread::sleep(Duration::from_secs(timing as u64));
-                atomic.fetch_add(
-                    if rand { random_range(1..len) } else { 1 },
-                    Ordering::Acquire,
-                );
-
-                send.send(()).expect("Failed to redraw");
+    if !once {
+        state.start_rotations();
+    }
 
-                for damaged in targets.iter() {
-                    damaged.store(true, Ordering::Release);
-                }
-            }));
-        }
+    // First roundtrip binds the outputs, the second receives their info
+    for _ in 0..2 {
+        event_queue.roundtrip(&mut state).unwrap();
+    }
+    if once && !state.targets_connected() {
+        warn!("no connected output matches a display targeted by a render pass");
+        return ExitCode::FAILURE;
     }
 
     while !state.exit {
@@ -156,14 +209,26 @@ fn main() {
         let event = match ret {
             Ok(_) => events[0],
             Err(Errno::EINTR) => continue,
-            Err(err) => Err(err).unwrap(),
+            Err(err) => panic!("epoll wait failed: {err}"),
         };
 
         match event.data().into() {
             EventKind::Mq => {
                 std::mem::drop(wayland_read_guard);
-                let _ = mq_recv.recv().unwrap();
-                state.draw(&qh);
+                let message = mq_recv.recv().unwrap();
+                state.handle_message(message, &qh);
+            }
+            EventKind::Watch => {
+                std::mem::drop(wayland_read_guard);
+                if state.watcher.changed() {
+                    let _ = state.reload(&qh);
+                }
+            }
+            EventKind::Ipc => {
+                std::mem::drop(wayland_read_guard);
+                if let Some(server) = &ipc_server {
+                    server.accept(&state.mq_send);
+                }
             }
             EventKind::Wayland => {
                 if wayland_read_guard.read().is_ok() {
@@ -172,5 +237,14 @@ fn main() {
             }
             EventKind::Unknown => error!("unknown event queue msg"),
         }
+
+        if state.once && state.drawn() {
+            state.exit = true;
+        }
     }
+
+    // Send any final buffers before exiting
+    event_queue.flush().unwrap();
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,268 @@
+//! Matching outputs to `[displays.*]` entries
+//!
+//! Entries may match on `name`, `make`, `model`, `serial` and `description`,
+//! each either a glob or `{ regex = "..." }`. Every given matcher must match.
+//! When several entries match an output the most specific wins, with serial
+//! ahead of make and model, then description, then name. Matchers such as
+//! `name = "*"` match everything, as if unset. Entries without any other
+//! matchers are fallbacks, used for outputs no other entry claims.
+
+use crate::config::DisplayTarget;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+use smithay_client_toolkit::output::OutputInfo;
+use std::collections::HashMap;
+
+/// Pattern matched against an output property
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "MatcherConfig")]
+pub enum Matcher {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MatcherConfig {
+    Glob(String),
+    Regex { regex: String },
+}
+
+impl Matcher {
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            Matcher::Glob(glob) => glob.is_match(value),
+            Matcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+
+    /// Whether the pattern matches any value, like `*`
+    pub fn matches_all(&self) -> bool {
+        match self {
+            Matcher::Glob(_) => {
+                !self.pattern().is_empty() && self.pattern().chars().all(|c| c == '*')
+            }
+            Matcher::Regex(_) => false,
+        }
+    }
+
+    fn pattern(&self) -> &str {
+        match self {
+            Matcher::Glob(glob) => glob.glob().glob(),
+            Matcher::Regex(regex) => regex.as_str(),
+        }
+    }
+}
+
+impl TryFrom<MatcherConfig> for Matcher {
+    type Error = String;
+
+    fn try_from(config: MatcherConfig) -> Result<Self, Self::Error> {
+        match config {
+            MatcherConfig::Glob(glob) => Glob::new(&glob)
+                .map(|g| Matcher::Glob(g.compile_matcher()))
+                .map_err(|e| e.to_string()),
+            // Regexes match the whole value, like globs
+            MatcherConfig::Regex { regex } => Regex::new(&regex)
+                .and_then(|_| Regex::new(&format!("^(?:{regex})$")))
+                .map(Matcher::Regex)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl PartialEq for Matcher {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Matcher::Glob(_), Matcher::Glob(_)) | (Matcher::Regex(_), Matcher::Regex(_))
+        ) && self.pattern() == other.pattern()
+    }
+}
+
+/// Output properties matched by display entries
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputProperties<'a> {
+    pub name: Option<&'a str>,
+    pub make: &'a str,
+    pub model: &'a str,
+    pub description: Option<&'a str>,
+}
+
+impl<'a> From<&'a OutputInfo> for OutputProperties<'a> {
+    fn from(info: &'a OutputInfo) -> Self {
+        OutputProperties {
+            name: info.name.as_deref(),
+            make: &info.make,
+            model: &info.model,
+            description: info.description.as_deref(),
+        }
+    }
+}
+
+/// Matcher narrowing which outputs match, `None` if unset or matching all
+fn specific(matcher: &Option<Matcher>) -> Option<&Matcher> {
+    matcher.as_ref().filter(|m| !m.matches_all())
+}
+
+impl DisplayTarget {
+    /// Whether every matcher of this entry matches the output
+    ///
+    /// Outputs don't report serials directly, so serials are matched against
+    /// the words of the description, where compositors include them.
+    pub fn matches(&self, output: &OutputProperties) -> bool {
+        let property = |matcher: &Option<Matcher>, value: Option<&str>| match specific(matcher) {
+            Some(matcher) => value.is_some_and(|v| matcher.is_match(v)),
+            None => true,
+        };
+
+        let serial = match specific(&self.serial) {
+            Some(matcher) => output.description.is_some_and(|d| {
+                d.split_whitespace()
+                    .map(|word| word.trim_matches(|c| c == '(' || c == ')'))
+                    .any(|word| matcher.is_match(word))
+            }),
+            None => true,
+        };
+
+        property(&self.name, output.name)
+            && property(&self.make, Some(output.make))
+            && property(&self.model, Some(output.model))
+            && property(&self.description, output.description)
+            && serial
+    }
+
+    /// Rank of the most specific matcher, higher wins
+    pub fn precedence(&self) -> u8 {
+        if specific(&self.serial).is_some() {
+            4
+        } else if specific(&self.make).is_some() || specific(&self.model).is_some() {
+            3
+        } else if specific(&self.description).is_some() {
+            2
+        } else if specific(&self.name).is_some() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Whether this entry matches every output, drawing to unclaimed outputs
+    pub fn is_fallback(&self) -> bool {
+        self.precedence() == 0
+    }
+}
+
+/// Display idents of the entries matching an output, most specific first
+///
+/// Entries of equal precedence are ordered by ident so the choice is stable.
+pub fn match_output<'a, 'b>(
+    displays: &'a HashMap<String, DisplayTarget>,
+    output: impl Into<OutputProperties<'b>>,
+) -> Vec<&'a String> {
+    let output = output.into();
+    let mut matched = displays
+        .iter()
+        .filter(|(_, display)| display.matches(&output))
+        .collect::<Vec<_>>();
+
+    matched.sort_by(|(a_ident, a), (b_ident, b)| {
+        b.precedence()
+            .cmp(&a.precedence())
+            .then_with(|| a_ident.cmp(b_ident))
+    });
+
+    matched.into_iter().map(|(ident, _)| ident).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn displays(entries: &[(&str, &str)]) -> HashMap<String, DisplayTarget> {
+        entries
+            .iter()
+            .map(|(ident, toml)| (ident.to_string(), toml::from_str(toml).unwrap()))
+            .collect()
+    }
+
+    const DELL: OutputProperties = OutputProperties {
+        name: Some("DP-1"),
+        make: "Dell Inc.",
+        model: "DELL U2720Q",
+        description: Some("Dell Inc. DELL U2720Q AB12345 (DP-1)"),
+    };
+
+    #[test]
+    fn most_specific_entry_matches_first() {
+        let displays = displays(&[
+            ("by_name", "name = 'DP-1'"),
+            ("by_description", "description = '*U2720Q*'"),
+            ("by_make", "make = 'Dell*'"),
+            ("by_serial", "serial = 'AB12345'"),
+            ("default", ""),
+        ]);
+
+        assert_eq!(
+            match_output(&displays, DELL),
+            [
+                "by_serial",
+                "by_make",
+                "by_description",
+                "by_name",
+                "default"
+            ]
+        );
+    }
+
+    #[test]
+    fn every_matcher_must_match() {
+        let displays = displays(&[
+            (
+                "both",
+                "name = 'DP-1'\nmodel = { regex = 'DELL U27[0-9]{2}Q' }",
+            ),
+            ("wrong_model", "name = 'DP-1'\nmodel = 'LG*'"),
+            ("unknown_serial", "serial = 'ZZ999'"),
+        ]);
+
+        assert_eq!(match_output(&displays, DELL), ["both"]);
+    }
+
+    #[test]
+    fn wildcard_entries_are_fallbacks() {
+        let displays = displays(&[
+            ("primary", "name = 'DP-1'"),
+            ("any", "name = '*'"),
+            ("any_model", "model = '**'"),
+            ("default", "bezel = { left = 8 }"),
+            ("prefix", "name = 'DP-*'"),
+        ]);
+
+        for ident in ["any", "any_model", "default"] {
+            assert!(displays[ident].is_fallback(), "{ident}");
+        }
+        for ident in ["primary", "prefix"] {
+            assert!(!displays[ident].is_fallback(), "{ident}");
+        }
+
+        // Fallbacks come after every other match
+        assert_eq!(
+            match_output(&displays, DELL),
+            ["prefix", "primary", "any", "any_model", "default"]
+        );
+
+        // Wildcards match outputs without a name too
+        let unnamed = OutputProperties {
+            name: None,
+            make: "LG",
+            model: "27GL850",
+            description: None,
+        };
+        assert_eq!(
+            match_output(&displays, unnamed),
+            ["any", "any_model", "default"]
+        );
+    }
+}
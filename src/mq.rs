@@ -1,3 +1,10 @@
+use crate::{
+    decode::DecodeKey,
+    ipc::{Request, Response},
+    resize::ResizeKey,
+};
+use cgmath::Vector2;
+use image::RgbaImage;
 use std::{
     error::Error,
     sync::{mpsc, Arc},
@@ -57,11 +64,36 @@ impl<T: Clone> MqReceiver<T> {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Rotation timer for a render pass elapsed
+    Rotate(usize),
+    /// Prefetch at a scale finished, `None` if it failed
+    Decoded(DecodeKey, f64, Option<Arc<RgbaImage>>),
+    /// Control socket request, answered on the sender
+    Request(Request, mpsc::Sender<Response>),
+    /// Resize job finished, with `None` for resizes which failed
+    Resized {
+        resizes: Vec<(ResizeKey, Option<RgbaImage>)>,
+        /// Image decoded for the resizes, at a scale
+        decoded: Option<(DecodeKey, f64, Arc<RgbaImage>)>,
+    },
+    /// Composite job finished
+    Composited {
+        display: String,
+        serial: u64,
+        /// Size and ARGB8888 pixels ready to present, `None` if it failed
+        buffer: Option<(Vector2<u32>, Vec<u8>)>,
+    },
+}
+
 #[repr(u64)]
 pub enum EventKind {
     Unknown,
     Wayland,
     Mq,
+    Watch,
+    Ipc,
 }
 
 impl From<u64> for EventKind {
@@ -69,6 +101,8 @@ impl From<u64> for EventKind {
         match value {
             value if value == Self::Wayland as u64 => Self::Wayland,
             value if value == Self::Mq as u64 => Self::Mq,
+            value if value == Self::Watch as u64 => Self::Watch,
+            value if value == Self::Ipc as u64 => Self::Ipc,
             _ => Self::Unknown,
         }
     }
@@ -0,0 +1,76 @@
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry,
+    output::{OutputHandler, OutputInfo, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+use std::error::Error;
+use wayland_client::{globals::registry_queue_init, protocol::wl_output, Connection, QueueHandle};
+
+/// Minimal Wayland state for enumerating outputs
+struct OutputList {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+/// Print every connected output with the details usable in `[displays.*]`
+pub fn print_outputs() -> Result<(), Box<dyn Error>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
+    let qh = event_queue.handle();
+
+    let mut list = OutputList {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+
+    // First roundtrip binds the outputs, the second receives their info
+    event_queue.roundtrip(&mut list)?;
+    event_queue.roundtrip(&mut list)?;
+
+    for output in list.output_state.outputs() {
+        if let Some(info) = list.output_state.info(&output) {
+            print_info(&info);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_info(info: &OutputInfo) {
+    println!("{}", info.name.as_deref().unwrap_or("<unnamed>"));
+    if let Some(description) = &info.description {
+        println!("  description: {description}");
+    }
+    println!("  make: {}", info.make);
+    println!("  model: {}", info.model);
+    if let Some((x, y)) = info.logical_position {
+        println!("  position: {x},{y}");
+    }
+    if let Some((w, h)) = info.logical_size {
+        println!("  size: {w}x{h}");
+    }
+    println!("  scale: {}", info.scale_factor);
+}
+
+impl OutputHandler for OutputList {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl ProvidesRegistryState for OutputList {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_output!(OutputList);
+delegate_registry!(OutputList);
@@ -1,6 +1,6 @@
 use cgmath::Vector2;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Region {
     pub min: Vector2<i32>,
     pub max: Vector2<i32>,
@@ -0,0 +1,339 @@
+use crate::{
+    config::{Anchor, Background, ImageSource, LoadedImage, Resize, ResizeKind},
+    decode,
+    disk_cache::DiskCache,
+    display::scale_dim,
+    mq::Message,
+    region::{Region, TupleVecExt},
+};
+use cgmath::Vector2;
+use image::{
+    imageops::{self, FilterType},
+    Rgba, RgbaImage,
+};
+use log::error;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+/// Blurred backgrounds are blurred at a fraction of the target size
+const BLUR_REDUCTION: u32 = 8;
+const BLUR_SIGMA: f32 = 4.0;
+
+/// Identifies a resized image, so it is only recomputed when changed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResizeKey {
+    pub image: String,
+    pub region: Region,
+    /// Scale in 120ths
+    pub scale: u32,
+    pub resize: Resize,
+}
+
+/// Resizes of one image drawn on a worker
+///
+/// The image is decoded at most once for all of them, and only if a resize
+/// isn't in the disk cache.
+pub struct ResizeJob {
+    pub image: LoadedImage,
+    /// Decoded pixels from the image cache
+    pub decoded: Option<Arc<RgbaImage>>,
+    /// Fraction of the image's size to decode, see [`Resize::needed_scale`]
+    pub scale: f64,
+    pub keys: Vec<ResizeKey>,
+    pub disk_cache: DiskCache,
+}
+
+impl ResizeJob {
+    /// Draw the resizes, reporting all of them as failed if drawing panics
+    pub fn run(self) -> Message {
+        let keys = self.keys.clone();
+        let ident = keys.first().map(|key| key.image.clone());
+        panic::catch_unwind(AssertUnwindSafe(|| self.draw())).unwrap_or_else(|_| {
+            error!("failed to resize image '{}'", ident.unwrap_or_default());
+            Message::Resized {
+                resizes: keys.into_iter().map(|key| (key, None)).collect(),
+                decoded: None,
+            }
+        })
+    }
+
+    fn draw(mut self) -> Message {
+        let mut decoded = None;
+        let resizes = std::mem::take(&mut self.keys)
+            .into_iter()
+            .map(|key| {
+                let dim = scale_dim(key.region.dim, key.scale);
+                if let Some(cached) = self.disk_cache.load(&self.image, &key, dim) {
+                    return (key, Some(cached.to_image()));
+                }
+
+                let resized = render(
+                    &self.image,
+                    self.decode(&key, &mut decoded),
+                    dim,
+                    &key.resize,
+                );
+                if let Some(resized) = &resized {
+                    self.disk_cache.store(&self.image, &key, resized);
+                }
+                (key, resized)
+            })
+            .collect();
+
+        Message::Resized {
+            resizes,
+            decoded: decoded
+                .flatten()
+                .zip(self.image.decode_key())
+                .map(|(image, key)| (key, self.scale, image)),
+        }
+    }
+
+    /// Pixels of an image file, decoded on first use
+    fn decode<'a>(
+        &self,
+        key: &ResizeKey,
+        decoded: &'a mut Option<Option<Arc<RgbaImage>>>,
+    ) -> Option<&'a RgbaImage> {
+        let ImageSource::File { path, .. } = &self.image.source else {
+            return None;
+        };
+
+        decoded
+            .get_or_insert_with(|| {
+                self.decoded.clone().or_else(|| {
+                    decode::decode(path, self.scale, key.resize.filter.into())
+                        .inspect_err(|e| error!("failed to decode image {path:?}: {e}"))
+                        .ok()
+                        .map(Arc::new)
+                })
+            })
+            .as_deref()
+    }
+}
+
+/// Draw an image at the given dimensions, `None` if a file isn't decoded
+///
+/// Generated images are drawn at the target size, so need no resizing.
+pub fn render(
+    image: &LoadedImage,
+    decoded: Option<&RgbaImage>,
+    dim: Vector2<u32>,
+    resize: &Resize,
+) -> Option<RgbaImage> {
+    match &image.source {
+        ImageSource::File { .. } => Some(self::resize(decoded?, dim, resize, image.focus)),
+        ImageSource::Color(color) => Some(RgbaImage::from_pixel(dim.x, dim.y, Rgba(*color))),
+        ImageSource::Gradient(gradient) => Some(gradient.render(dim)),
+    }
+}
+
+impl Resize {
+    /// Fraction of an image's size used when drawing it at the given dimensions
+    ///
+    /// Modes drawing the image unscaled always need all of it.
+    pub fn needed_scale(&self, image: Vector2<u32>, dim: Vector2<u32>) -> f64 {
+        let ratio = dim.map(f64::from).zip(image.map(f64::from), |d, i| d / i);
+        match self.kind {
+            ResizeKind::Cover | ResizeKind::Stretch => f64::max(ratio.x, ratio.y),
+            ResizeKind::Contain => f64::min(ratio.x, ratio.y),
+            ResizeKind::Tile | ResizeKind::Center | ResizeKind::None => 1.0,
+        }
+    }
+}
+
+/// Fit an image to the given dimensions
+///
+/// The focus is a normalized point of the image kept in view when cropping,
+/// taking priority over the anchor.
+pub fn resize(
+    image: &RgbaImage,
+    dim: Vector2<u32>,
+    resize: &Resize,
+    focus: Option<Vector2<f64>>,
+) -> RgbaImage {
+    match resize.kind {
+        ResizeKind::Cover => cover(image, dim, resize.filter.into(), resize.anchor, focus),
+        ResizeKind::Stretch => imageops::resize(image, dim.x, dim.y, resize.filter.into()),
+        ResizeKind::Contain => {
+            let original_dims = image.dimensions().to_vec2().map(f64::from);
+
+            let scale = dim.map(f64::from).zip(original_dims, |l, r| l / r);
+            let scale = f64::min(scale.x, scale.y);
+
+            let new_dims = (original_dims * scale).map(|i| (i.round() as u32).max(1));
+            let scaled = imageops::resize(image, new_dims.x, new_dims.y, resize.filter.into());
+
+            let space = dim.zip(new_dims, |d, n| f64::from(d.saturating_sub(n)));
+            let offset = space.zip(resize.anchor.fraction(), |s, a| (s * a).round() as i64);
+
+            let mut canvas = background(image, dim, resize.background);
+            imageops::overlay(&mut canvas, &scaled, offset.x, offset.y);
+            canvas
+        }
+        ResizeKind::Tile => {
+            // Integer scaling keeps pixel art sharp regardless of filter
+            let scale = resize.tile_scale;
+            let tile = if scale > 1 {
+                let (width, height) = image.dimensions();
+                &imageops::resize(image, width * scale, height * scale, FilterType::Nearest)
+            } else {
+                image
+            };
+
+            let mut canvas = RgbaImage::new(dim.x, dim.y);
+            imageops::tile(&mut canvas, tile);
+            canvas
+        }
+        ResizeKind::Center => {
+            let (width, height) = image.dimensions();
+
+            let mut canvas = background(image, dim, resize.background);
+            imageops::overlay(
+                &mut canvas,
+                image,
+                (dim.x as i64 - width as i64) / 2,
+                (dim.y as i64 - height as i64) / 2,
+            );
+            canvas
+        }
+        ResizeKind::None => {
+            let mut canvas = background(image, dim, resize.background);
+            imageops::overlay(&mut canvas, image, 0, 0);
+            canvas
+        }
+    }
+}
+
+/// Scale preserving aspect ratio to fill the dimensions, cropping the edges
+fn cover(
+    image: &RgbaImage,
+    dim: Vector2<u32>,
+    filter: FilterType,
+    anchor: Anchor,
+    focus: Option<Vector2<f64>>,
+) -> RgbaImage {
+    let original_dims = image.dimensions().to_vec2().map(f64::from);
+
+    let scale = dim.map(f64::from).zip(original_dims, |l, r| l / r);
+    let scale = f64::max(scale.x, scale.y);
+
+    let new_dims = (original_dims * scale).map(|i| (i.round() as u32).max(1));
+
+    let temp_image = imageops::resize(image, new_dims.x, new_dims.y, filter);
+
+    let offset = crop_offset(new_dims, dim, anchor, focus);
+    imageops::crop_imm(&temp_image, offset.x, offset.y, dim.x, dim.y).to_image()
+}
+
+/// Top-left of the visible area within a scaled image
+///
+/// Centres on the focus where possible, otherwise aligns to the anchor.
+fn crop_offset(
+    scaled: Vector2<u32>,
+    dim: Vector2<u32>,
+    anchor: Anchor,
+    focus: Option<Vector2<f64>>,
+) -> Vector2<u32> {
+    let scaled = scaled.map(f64::from);
+    let dim = dim.map(f64::from);
+    let excess = scaled.zip(dim, |s, d| (s - d).max(0.0));
+
+    let offset = match focus {
+        Some(focus) => scaled.zip(focus, |s, f| s * f) - dim / 2.0,
+        None => excess.zip(anchor.fraction(), |e, a| e * a),
+    };
+
+    offset.zip(excess, |o, e| o.clamp(0.0, e).round() as u32)
+}
+
+fn background(image: &RgbaImage, dim: Vector2<u32>, background: Background) -> RgbaImage {
+    match background {
+        Background::Color(color) => RgbaImage::from_pixel(dim.x, dim.y, Rgba(color)),
+        Background::Transparent => RgbaImage::new(dim.x, dim.y),
+        Background::Blur => {
+            let small = dim.map(|i| (i / BLUR_REDUCTION).max(1));
+            let small = cover(image, small, FilterType::Triangle, Anchor::Center, None);
+            let blurred = imageops::fast_blur(&small, BLUR_SIGMA);
+            imageops::resize(&blurred, dim.x, dim.y, FilterType::Triangle)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_offset_follows_anchor() {
+        let scaled = Vector2::new(300, 100);
+        let dim = Vector2::new(100, 100);
+        let offset = |anchor| crop_offset(scaled, dim, anchor, None);
+
+        assert_eq!(offset(Anchor::Center), Vector2::new(100, 0));
+        assert_eq!(offset(Anchor::Left), Vector2::new(0, 0));
+        assert_eq!(offset(Anchor::BottomRight), Vector2::new(200, 0));
+    }
+
+    #[test]
+    fn crop_offset_centres_on_focus() {
+        let scaled = Vector2::new(300, 200);
+        let dim = Vector2::new(100, 100);
+        let offset = |x, y| crop_offset(scaled, dim, Anchor::TopLeft, Some(Vector2::new(x, y)));
+
+        assert_eq!(offset(0.5, 0.5), Vector2::new(100, 50));
+        assert_eq!(offset(0.25, 0.75), Vector2::new(25, 100));
+    }
+
+    #[test]
+    fn crop_offset_keeps_focus_at_edges_inside_image() {
+        let scaled = Vector2::new(300, 200);
+        let dim = Vector2::new(100, 100);
+        let offset = |x, y| crop_offset(scaled, dim, Anchor::Center, Some(Vector2::new(x, y)));
+
+        assert_eq!(offset(0.0, 0.0), Vector2::new(0, 0));
+        assert_eq!(offset(1.0, 1.0), Vector2::new(200, 100));
+        assert_eq!(offset(0.0, 1.0), Vector2::new(0, 100));
+        // Out of range focal points are clamped the same way
+        assert_eq!(offset(-1.0, 2.0), Vector2::new(0, 100));
+    }
+
+    #[test]
+    fn crop_offset_of_exact_fit_is_zero() {
+        let dim = Vector2::new(100, 100);
+        let focus = Some(Vector2::new(1.0, 0.0));
+
+        assert_eq!(
+            crop_offset(dim, dim, Anchor::BottomRight, None),
+            Vector2::new(0, 0)
+        );
+        assert_eq!(
+            crop_offset(dim, dim, Anchor::Center, focus),
+            Vector2::new(0, 0)
+        );
+    }
+
+    #[test]
+    fn needed_scale_depends_on_kind() {
+        let needed = |kind| {
+            let resize = Resize {
+                kind,
+                filter: Default::default(),
+                anchor: Anchor::Center,
+                background: Background::Transparent,
+                tile_scale: 1,
+            };
+            resize.needed_scale(Vector2::new(4000, 2000), Vector2::new(1000, 1000))
+        };
+
+        // Covering fills the larger ratio, containing fits the smaller
+        assert_eq!(needed(ResizeKind::Cover), 0.5);
+        assert_eq!(needed(ResizeKind::Stretch), 0.5);
+        assert_eq!(needed(ResizeKind::Contain), 0.25);
+        for kind in [ResizeKind::Tile, ResizeKind::Center, ResizeKind::None] {
+            assert_eq!(needed(kind), 1.0);
+        }
+    }
+}
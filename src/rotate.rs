@@ -0,0 +1,45 @@
+use crate::mq::{Message, MqSender};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Periodically requests a render pass to rotate, stopped when dropped
+pub struct RotateTimer {
+    /// Set when dropped, waking the thread so it exits immediately
+    cancelled: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl RotateTimer {
+    pub fn spawn(pass: usize, interval: Duration, send: MqSender<Message>) -> Self {
+        let cancelled = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let thread_cancelled = cancelled.clone();
+        std::thread::spawn(move || {
+            let (lock, condvar) = &*thread_cancelled;
+            let mut cancelled = lock.lock().unwrap();
+            loop {
+                cancelled = condvar
+                    .wait_timeout_while(cancelled, interval, |cancelled| !*cancelled)
+                    .unwrap()
+                    .0;
+
+                if *cancelled {
+                    return;
+                }
+
+                send.send(Message::Rotate(pass)).expect("Failed to redraw");
+            }
+        });
+
+        RotateTimer { cancelled }
+    }
+}
+
+impl Drop for RotateTimer {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.cancelled;
+        *lock.lock().unwrap() = true;
+        condvar.notify_one();
+    }
+}
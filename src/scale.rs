@@ -0,0 +1,104 @@
+//! Fractional scaling with wp_fractional_scale_v1 and wp_viewporter
+//!
+//! Buffers are rendered at the preferred scale and a viewport maps them back
+//! to the logical size of the surface.
+
+use crate::state::State;
+use smithay_client_toolkit::{
+    reexports::protocols::wp::{
+        fractional_scale::v1::client::{
+            wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+            wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+        },
+        viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+    },
+    shell::WaylandSurface,
+};
+use std::sync::atomic::Ordering;
+use wayland_client::{
+    delegate_noop, globals::GlobalList, protocol::wl_surface::WlSurface, Connection, Dispatch,
+    QueueHandle,
+};
+
+/// Scales are sent as a numerator over 120
+pub const SCALE_DENOMINATOR: u32 = 120;
+
+/// Bound fractional scale and viewporter globals
+pub struct FractionalScale {
+    manager: WpFractionalScaleManagerV1,
+    viewporter: WpViewporter,
+}
+
+/// Per surface fractional scale objects
+pub struct SurfaceScale {
+    pub viewport: WpViewport,
+    pub fractional: WpFractionalScaleV1,
+    /// Preferred scale in 120ths, unset until the compositor sends one
+    pub preferred: Option<u32>,
+}
+
+impl FractionalScale {
+    /// Bind both globals, or neither if the compositor lacks one
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<State>) -> Option<FractionalScale> {
+        let manager = globals.bind(qh, 1..=1, ()).ok()?;
+        let viewporter = globals.bind(qh, 1..=1, ()).ok()?;
+
+        Some(FractionalScale {
+            manager,
+            viewporter,
+        })
+    }
+
+    pub fn create(&self, surface: &WlSurface, qh: &QueueHandle<State>) -> SurfaceScale {
+        SurfaceScale {
+            viewport: self.viewporter.get_viewport(surface, qh, ()),
+            fractional: self
+                .manager
+                .get_fractional_scale(surface, qh, surface.clone()),
+            preferred: None,
+        }
+    }
+}
+
+impl Drop for SurfaceScale {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        self.fractional.destroy();
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+
+        for disp in state.displays.values_mut() {
+            if disp.layer.0.wl_surface() != surface {
+                continue;
+            }
+
+            let Some(surface_scale) = &mut disp.surface_scale else {
+                continue;
+            };
+
+            if surface_scale.preferred != Some(scale) {
+                surface_scale.preferred = Some(scale);
+                disp.damaged.store(true, Ordering::Release);
+            }
+        }
+
+        state.draw(qh);
+    }
+}
+
+delegate_noop!(State: WpFractionalScaleManagerV1);
+delegate_noop!(State: WpViewporter);
+delegate_noop!(State: WpViewport);
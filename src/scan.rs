@@ -0,0 +1,121 @@
+//! Expanding directories and globs to image files
+//!
+//! A directory lists the images directly inside it, or every image below it
+//! when recursive. A glob is matched against paths relative to the directory
+//! before its first wildcard, so `walls/**/*.jpg` searches all of `walls`.
+//! Files are only included if their extension is a supported image format.
+
+use globset::GlobBuilder;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+/// Image files found by a scan, sorted by path
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Directory the images are relative to
+    pub root: PathBuf,
+    pub files: Vec<PathBuf>,
+    /// Directories searched, watched for new images
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Whether a path contains glob wildcards
+pub fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+/// Split a glob into the directory before its first wildcard and the rest
+pub fn split_glob(path: &Path) -> (PathBuf, PathBuf) {
+    let mut root = PathBuf::new();
+    let mut components = path.components();
+    for component in components.by_ref() {
+        let component = Path::new(component.as_os_str());
+        if is_glob(component) {
+            let rest = components.as_path();
+            return match rest.as_os_str().is_empty() {
+                true => (root, component.to_path_buf()),
+                false => (root, component.join(rest)),
+            };
+        }
+        root.push(component);
+    }
+
+    (root, PathBuf::new())
+}
+
+/// Images in a directory
+pub fn scan_dir(dir: &Path, recursive: bool) -> Scan {
+    let mut scan = Scan {
+        root: dir.to_path_buf(),
+        ..Scan::default()
+    };
+    walk(dir, recursive, &mut scan);
+    scan.files.sort();
+    scan
+}
+
+/// Images below `root` matching a glob relative to it
+pub fn scan_glob(root: &Path, pattern: &Path) -> Result<Scan, globset::Error> {
+    let glob = GlobBuilder::new(&pattern.to_string_lossy())
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+
+    // Patterns without a separator only match directly inside the root
+    let recursive = pattern.components().count() > 1 || pattern.to_string_lossy().contains("**");
+    let mut scan = scan_dir(root, recursive);
+    scan.files
+        .retain(|file| file.strip_prefix(root).is_ok_and(|f| glob.is_match(f)));
+
+    Ok(scan)
+}
+
+fn walk(dir: &Path, recursive: bool, scan: &mut Scan) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    scan.dirs.push(dir.to_path_buf());
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Symlinked directories aren't followed, so cycles can't occur
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if is_dir {
+            if recursive {
+                walk(&path, recursive, scan);
+            }
+        } else if path.is_file() && ImageFormat::from_path(&path).is_ok() {
+            scan.files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(path: &str) -> (PathBuf, PathBuf) {
+        split_glob(Path::new(path))
+    }
+
+    #[test]
+    fn globs_split_at_the_first_wildcard_component() {
+        assert_eq!(split("walls/*.jpg"), ("walls".into(), "*.jpg".into()));
+        assert_eq!(
+            split("/home/wani/walls/**/*.png"),
+            ("/home/wani/walls".into(), "**/*.png".into())
+        );
+        assert_eq!(
+            split("walls/day?/dusk/*.png"),
+            ("walls".into(), "day?/dusk/*.png".into())
+        );
+        assert_eq!(split("{a,b}/x.png"), ("".into(), "{a,b}/x.png".into()));
+        assert_eq!(split("walls/[0-9]"), ("walls".into(), "[0-9]".into()));
+    }
+
+    #[test]
+    fn paths_without_wildcards_are_all_root() {
+        assert!(!is_glob(Path::new("walls/day.png")));
+        assert_eq!(split("walls/day.png"), ("walls/day.png".into(), "".into()));
+    }
+}
@@ -0,0 +1,58 @@
+//! Solid colours with wp_single_pixel_buffer_v1
+//!
+//! A one pixel buffer is stretched over the surface by its viewport, so a
+//! solid colour needs no shared memory of the output's size.
+
+use crate::state::State;
+use smithay_client_toolkit::reexports::protocols::wp::single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1;
+use wayland_client::{
+    delegate_noop,
+    globals::GlobalList,
+    protocol::wl_buffer::{self, WlBuffer},
+    Connection, Dispatch, QueueHandle,
+};
+
+/// Bound single pixel buffer global
+pub struct SinglePixel {
+    manager: WpSinglePixelBufferManagerV1,
+}
+
+impl SinglePixel {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<State>) -> Option<SinglePixel> {
+        let manager = globals.bind(qh, 1..=1, ()).ok()?;
+        Some(SinglePixel { manager })
+    }
+
+    /// Buffer of a single straight alpha colour, destroyed once released
+    pub fn create(&self, color: [u8; 4], qh: &QueueHandle<State>) -> WlBuffer {
+        // Channels span the full u32 range and are premultiplied
+        let alpha = u32::from(color[3]);
+        let channel = |c: u8| u32::from(c) * alpha / 255 * (u32::MAX / 255);
+
+        self.manager.create_u32_rgba_buffer(
+            channel(color[0]),
+            channel(color[1]),
+            channel(color[2]),
+            alpha * (u32::MAX / 255),
+            qh,
+            (),
+        )
+    }
+}
+
+impl Dispatch<WlBuffer, ()> for State {
+    fn event(
+        _state: &mut Self,
+        proxy: &WlBuffer,
+        event: wl_buffer::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            proxy.destroy();
+        }
+    }
+}
+
+delegate_noop!(State: WpSinglePixelBufferManagerV1);
@@ -1,16 +1,32 @@
 use crate::{
-    config::{Config, DisplayGroup, RenderSource, RenderTarget, ResizeKind},
-    display::Display,
-    region::{Region, TupleVecExt},
+    composite::{self, CompositeJob, CompositeKey, LayerSource, PendingComposites},
+    config::{
+        BezelUnit, Config, ConfigError, GroupDisplays, ImageSource, LoadedImage, RenderPass,
+        RenderSource, RenderTarget,
+    },
+    decode::ImageCache,
+    disk_cache::DiskCache,
+    display::{scale_dim, swaps_axes, Display},
+    layout::{Canvas, LayoutInput},
+    matcher::match_output,
+    mq::{Message, MqSender},
+    region::Region,
+    resize::{ResizeJob, ResizeKey},
+    rotate::RotateTimer,
+    scale::{FractionalScale, SCALE_DENOMINATOR},
+    single_pixel::SinglePixel,
+    watch::Watcher,
+    worker::WorkerPool,
 };
 use cgmath::Vector2;
-use image::{imageops::FilterType, RgbaImage};
-use log::{info, warn};
+use image::RgbaImage;
+use log::{error, info, warn};
+use rand::random_range;
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
     delegate_seat, delegate_shm,
-    output::{OutputHandler, OutputState},
+    output::{OutputHandler, OutputInfo, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
@@ -27,11 +43,12 @@ use smithay_client_toolkit::{
     shm::{multi::MultiPool, Shm, ShmHandler},
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 use wayland_client::{
     protocol::{
@@ -50,135 +67,809 @@ pub struct State {
     pub compositor_state: CompositorState,
     pub shm: Shm,
     pub layer_shell: LayerShell,
+    pub fractional_scale: Option<FractionalScale>,
+    pub single_pixel: Option<SinglePixel>,
 
     pub exit: bool,
+    pub once: bool,
     pub first_configure: bool,
     pub pointer: Option<wl_pointer::WlPointer>,
-
-    pub display_ident_map: HashMap<String, String>,
+    pub mq_send: MqSender<Message>,
+    pub watcher: Watcher,
 
     pub displays: HashMap<String, Display>,
-    pub render_pass_resizes: HashMap<usize, RgbaImage>,
-    pub render_pass_rotate_index: HashMap<usize, Arc<AtomicUsize>>,
+    pub composites: PendingComposites,
+    pub render_pass_resizes: HashMap<ResizeKey, Arc<RgbaImage>>,
+    /// Resizes being drawn by workers
+    pub render_pass_pending: HashSet<ResizeKey>,
+    /// Resizes whose image failed to decode, retried after a reload
+    pub render_pass_failed: HashSet<ResizeKey>,
+    pub workers: WorkerPool,
+    pub image_cache: ImageCache,
+    pub disk_cache: DiskCache,
+    pub render_pass_rotate_index: HashMap<usize, usize>,
+    /// Next image of randomly rotating render passes, chosen early to prefetch it
+    pub render_pass_next: HashMap<usize, usize>,
+    pub render_pass_timers: HashMap<usize, RotateTimer>,
+    pub render_pass_paused: HashSet<usize>,
+    pub render_pass_override: HashMap<usize, String>,
 }
 
 impl State {
     pub fn draw(&mut self, qh: &QueueHandle<Self>) {
+        let mut live_resizes = HashSet::new();
+        // Render pass, display, resize and crop of each layer, in render pass order
+        let mut layers = Vec::new();
+
         for (index, pass) in self.config.render_passes.iter().enumerate() {
-            let image = match &pass.source {
-                RenderSource::Single(image) => image,
-                RenderSource::Many { images, .. } => &{
-                    let rotate_index = self.render_pass_rotate_index.get(&index).unwrap();
-                    &images[rotate_index.load(Ordering::Acquire) % images.len()]
+            for canvas in self.target_canvases(&pass.target) {
+                let total_region = canvas.region;
+
+                let scale = self.canvas_scale(&canvas);
+
+                let key = ResizeKey {
+                    image: self.pass_image(index).unwrap().clone(),
+                    region: total_region,
+                    scale,
+                    resize: pass.resize,
+                };
+                live_resizes.insert(key.clone());
+
+                // Skip canvases with nothing to redraw
+                let damaged = canvas
+                    .displays
+                    .into_iter()
+                    .filter(|(d, _)| self.displays.get(d).is_some_and(Display::needs_draw))
+                    .collect::<Vec<_>>();
+                if damaged.is_empty() {
+                    continue;
+                }
+
+                for (display, crop) in damaged {
+                    layers.push((index, display, key.clone(), crop));
+                }
+            }
+        }
+
+        // Colours are never resized, displays showing only one are filled with it
+        let mut solid = HashMap::new();
+        for (index, display, key, _) in &layers {
+            let Some(mut color) = self.color(key) else {
+                continue;
+            };
+            if layers.iter().filter(|l| l.1 == *display).count() == 1 {
+                let opacity = self.config.render_passes[*index].opacity;
+                color[3] = (f32::from(color[3]) * opacity).round() as u8;
+                solid.insert(display.clone(), color);
+            }
+        }
+
+        // Displays showing a single cached resize as is copy it from disk,
+        // other missing resizes are drawn by workers, grouped by image
+        let mut mapped = HashMap::new();
+        let mut jobs: HashMap<&String, Vec<ResizeKey>> = HashMap::new();
+        for (index, display, key, crop) in &layers {
+            if solid.contains_key(display)
+                || self.color(key).is_some()
+                || self.render_pass_resizes.contains_key(key)
+                || self.render_pass_pending.contains(key)
+                || self.render_pass_failed.contains(key)
+            {
+                continue;
+            }
+
+            let image = &self.config.images[&key.image];
+            let dim = scale_dim(key.region.dim, key.scale);
+            let output = &self.displays[display];
+            let single = layers.iter().filter(|l| l.1 == *display).count() == 1;
+            let as_is = single
+                && *crop == key.region
+                && dim == output.buffer_dim()
+                && output.transform == wl_output::Transform::Normal
+                && self.config.render_passes[*index].opacity >= 1.0;
+
+            if as_is && let Some(cached) = self.disk_cache.load(image, key, dim) {
+                mapped.insert(display.clone(), cached);
+                continue;
+            }
+
+            self.render_pass_pending.insert(key.clone());
+            jobs.entry(&key.image).or_default().push(key.clone());
+        }
+
+        for (ident, keys) in jobs {
+            let image = &self.config.images[ident];
+            let scale = self.image_scale(ident).unwrap_or(1.0);
+            let decoded = image
+                .decode_key()
+                .and_then(|key| self.image_cache.get(&key, scale));
+
+            let job = ResizeJob {
+                image: image.clone(),
+                decoded,
+                scale,
+                keys,
+                disk_cache: self.disk_cache.clone(),
+            };
+            let send = self.mq_send.clone();
+            self.workers.spawn(move || {
+                let _ = send.send(job.run());
+            });
+        }
+
+        // Drop resizes no longer shown by any render pass
+        self.render_pass_resizes
+            .retain(|key, _| live_resizes.contains(key));
+        self.render_pass_failed
+            .retain(|key| live_resizes.contains(key));
+
+        // Displays keep their current buffer until every layer is resized,
+        // composites started before they changed are stale
+        let waiting = layers
+            .iter()
+            .filter(|(_, _, key, _)| self.render_pass_pending.contains(key))
+            .map(|(_, display, _, _)| display)
+            .collect::<HashSet<_>>();
+        for display in &waiting {
+            self.composites.cancel(display);
+        }
+
+        // Render passes in order of each display, composited on a worker
+        let mut composites: HashMap<&String, (CompositeKey, Vec<composite::Layer>)> =
+            HashMap::new();
+        for (index, display, key, crop) in &layers {
+            if solid.contains_key(display)
+                || mapped.contains_key(display)
+                || waiting.contains(display)
+            {
+                continue;
+            }
+            let source = match self.color(key) {
+                Some(color) => LayerSource::Color(color),
+                // Images failing to decode are left out
+                None => match self.render_pass_resizes.get(key) {
+                    Some(resized) => LayerSource::Resized(resized.clone()),
+                    None => continue,
                 },
             };
 
-            let image = &self.config.images.get(image).unwrap().image;
+            let output = &self.displays[display];
+            let pass = &self.config.render_passes[*index];
+            let (composite_key, composite_layers) =
+                composites.entry(display).or_insert_with(|| {
+                    let key = CompositeKey {
+                        dim: output.buffer_dim(),
+                        transform: output.transform,
+                        layers: Vec::new(),
+                    };
+                    (key, Vec::new())
+                });
+            composite_key.layers.push((*index, key.clone(), *crop));
+            composite_layers.push(composite::Layer {
+                source,
+                total: key.region,
+                crop: *crop,
+                scale: key.scale,
+                filter: pass.resize.filter.into(),
+                blend: pass.blend,
+                opacity: pass.opacity,
+            });
+        }
 
-            let total_region = match &pass.target {
-                RenderTarget::Display(d) => self.displays.get(d).unwrap().region,
-                RenderTarget::Group(g) => self
-                    .group_region(self.config.groups.get(g).unwrap())
-                    .unwrap(),
+        // Displays whose every image failed are filled black, rather than
+        // never receiving a buffer
+        let empty = layers
+            .iter()
+            .map(|(_, display, _, _)| display)
+            .filter(|d| !solid.contains_key(*d) && !mapped.contains_key(*d))
+            .filter(|d| !waiting.contains(d) && !composites.contains_key(d))
+            .collect::<HashSet<_>>();
+        for key in empty {
+            self.composites.cancel(key);
+            if let Some(display) = self.displays.get_mut(key)
+                && display.needs_draw()
+            {
+                warn!("no image could be drawn on display '{key}', filling it with black");
+                display.draw_color(qh, self.single_pixel.as_ref(), [0, 0, 0, 255]);
+            }
+        }
+
+        for (display, (key, layers)) in composites {
+            let (dim, transform) = (key.dim, key.transform);
+            let Some(serial) = self.composites.start(display, key) else {
+                continue;
             };
 
-            self.render_pass_resizes.insert(
-                index,
-                match pass.resize {
-                    ResizeKind::Cover => {
-                        let original_dims = image.dimensions().to_vec2().map(From::from);
-
-                        let scale = total_region
-                            .dim
-                            .zip(original_dims, |l, r| l as f64 / r as f64);
-                        let scale = f64::max(scale.x, scale.y);
-
-                        let new_dims = (original_dims * scale).map(|i| i.round() as u32);
-
-                        let temp_image = image::imageops::resize(
-                            image,
-                            new_dims.x,
-                            new_dims.y,
-                            FilterType::Nearest,
-                        );
-
-                        image::imageops::crop_imm(
-                            &temp_image,
-                            ((new_dims.x as i32 - total_region.dim.x) / 2).max(0) as u32,
-                            ((new_dims.y as i32 - total_region.dim.y) / 2).max(0) as u32,
-                            total_region.dim.x as u32,
-                            total_region.dim.y as u32,
-                        )
-                        .to_image()
+            let job = CompositeJob {
+                display: display.clone(),
+                serial,
+                dim,
+                transform,
+                layers,
+            };
+            let send = self.mq_send.clone();
+            self.workers.spawn(move || {
+                let _ = send.send(job.run());
+            });
+        }
+
+        for (display, cached) in mapped {
+            self.composites.cancel(&display);
+            if let Some(display) = self.displays.get_mut(&display) {
+                display.draw(qh, cached.dim, cached.pixels());
+            }
+        }
+
+        for (display, color) in solid {
+            self.composites.cancel(&display);
+            if let Some(display) = self.displays.get_mut(&display) {
+                display.draw_color(qh, self.single_pixel.as_ref(), color);
+            }
+        }
+    }
+
+    /// Colour of a solid colour image, drawn without a resize
+    fn color(&self, key: &ResizeKey) -> Option<[u8; 4]> {
+        match self.config.images[&key.image].source {
+            ImageSource::Color(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    /// Scale to render a canvas at, the highest so no display is upscaled
+    pub fn canvas_scale(&self, canvas: &Canvas) -> u32 {
+        canvas
+            .displays
+            .iter()
+            .filter_map(|(d, _)| self.displays.get(d).map(|d| d.scale()))
+            .max()
+            .unwrap_or(SCALE_DENOMINATOR)
+    }
+
+    /// Largest fraction of an image file's size any render pass draws it at
+    ///
+    /// `None` if no render pass using the image has a connected display.
+    pub fn image_scale(&self, ident: &String) -> Option<f64> {
+        let image = self.config.images.get(ident)?;
+        let ImageSource::File { dim, .. } = image.source else {
+            return None;
+        };
+
+        let mut scale = None;
+        for (index, pass) in self.config.render_passes.iter().enumerate() {
+            let uses = match &pass.source {
+                RenderSource::Single(image) => image == ident,
+                RenderSource::Many { images, .. } => images.contains(ident),
+            };
+            if !uses && self.render_pass_override.get(&index) != Some(ident) {
+                continue;
+            }
+
+            for canvas in self.target_canvases(&pass.target) {
+                let target = scale_dim(canvas.region.dim, self.canvas_scale(&canvas));
+                let needed = pass.resize.needed_scale(dim, target);
+                scale = Some(scale.map_or(needed, |s: f64| s.max(needed)));
+            }
+        }
+
+        scale
+    }
+
+    /// Canvases a render target spans a single image across
+    ///
+    /// Groups span one image, while a display entry matching several outputs
+    /// draws to each separately.
+    pub fn target_canvases(&self, target: &RenderTarget) -> Vec<Canvas> {
+        let displays = self.target_displays(target);
+        match target {
+            RenderTarget::Display(_) => displays
+                .into_iter()
+                .map(|d| {
+                    let region = self.displays[&d].region;
+                    Canvas {
+                        region,
+                        displays: vec![(d, region)],
+                    }
+                })
+                .collect(),
+            RenderTarget::Group(group) => {
+                let group = self.config.groups.get(group);
+                let gap = group.map_or(0, |g| g.gap);
+
+                // Size displays relative to the densest, so none are upscaled
+                let reference = group.filter(|g| g.physical_size).and_then(|_| {
+                    displays
+                        .iter()
+                        .filter_map(|d| self.displays[d].density())
+                        .reduce(f64::max)
+                });
+
+                let inputs = displays
+                    .iter()
+                    .map(|d| {
+                        let display = &self.displays[d];
+                        let factor = reference
+                            .zip(display.density())
+                            .map_or(1.0, |(reference, density)| reference / density);
+
+                        // Positions from the group layout replace the compositor's
+                        let region = match group.and_then(|g| g.layout.get(&display.entry)) {
+                            Some(min) => Region::new(*min, min + display.region.dim),
+                            None => display.region,
+                        };
+
+                        LayoutInput {
+                            region,
+                            size: display
+                                .region
+                                .dim
+                                .map(|i| (f64::from(i) * factor).round() as i32),
+                            bezel: display.bezel_insets().scale(factor),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                Canvas::layout(displays, &inputs, gap).into_iter().collect()
+            }
+        }
+    }
+
+    /// Keys of the connected displays a render target draws to
+    pub fn target_displays(&self, target: &RenderTarget) -> Vec<String> {
+        let mut displays = self
+            .displays
+            .iter()
+            .filter(|(_, display)| match target {
+                RenderTarget::Display(entry) => display.entry == *entry,
+                RenderTarget::Group(group) => {
+                    match self.config.groups.get(group).map(|g| &g.displays) {
+                        Some(GroupDisplays::All) => true,
+                        Some(GroupDisplays::Listed(entries)) => entries.contains(&display.entry),
+                        None => false,
                     }
-                    ResizeKind::Stretch => image::imageops::resize(
-                        image,
-                        total_region.dim.x as u32,
-                        total_region.dim.y as u32,
-                        FilterType::Nearest,
-                    ),
+                }
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        displays.sort();
+        displays
+    }
+
+    /// Mark the render passes drawing to a display for redrawing
+    ///
+    /// Their regions change as the display is added or removed.
+    fn damage_passes_with(&self, key: &str) {
+        for pass in &self.config.render_passes {
+            if self.target_displays(&pass.target).iter().any(|d| d == key) {
+                self.damage_target(&pass.target);
+            }
+        }
+    }
+
+    /// Connected displays drawn to by any render pass
+    fn targeted(&self) -> impl Iterator<Item = &Display> {
+        self.config
+            .render_passes
+            .iter()
+            .flat_map(|pass| self.target_displays(&pass.target))
+            .filter_map(|display| self.displays.get(&display))
+    }
+
+    /// Whether any render pass draws to a connected display
+    pub fn targets_connected(&self) -> bool {
+        self.targeted().next().is_some()
+    }
+
+    /// Whether every targeted display has been drawn
+    pub fn drawn(&self) -> bool {
+        self.targets_connected() && self.targeted().all(|d| !d.damaged.load(Ordering::Acquire))
+    }
+
+    /// Mark every display of a render target for redrawing
+    pub fn damage_target(&self, target: &RenderTarget) {
+        for display in self.target_displays(target) {
+            if let Some(display) = self.displays.get(&display) {
+                display.damaged.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Image ident currently shown by a render pass
+    pub fn pass_image(&self, index: usize) -> Option<&String> {
+        if let Some(image) = self.render_pass_override.get(&index) {
+            return Some(image);
+        }
+
+        match &self.config.render_passes.get(index)?.source {
+            RenderSource::Single(image) => Some(image),
+            RenderSource::Many { images, .. } => {
+                let rotate_index = self.render_pass_rotate_index.get(&index).unwrap_or(&0);
+                images.get(rotate_index % images.len())
+            }
+        }
+    }
+
+    /// Start a timer for every rotating render pass, replacing existing timers
+    pub fn start_rotations(&mut self) {
+        self.render_pass_timers.clear();
+
+        for index in 0..self.config.render_passes.len() {
+            self.restart_timer(index);
+        }
+    }
+
+    /// (Re)start the rotation timer of a render pass, unless it is paused
+    pub fn restart_timer(&mut self, index: usize) {
+        self.render_pass_timers.remove(&index);
+
+        if self.render_pass_paused.contains(&index) {
+            return;
+        }
+        self.prefetch(index);
+
+        if let Some(RenderPass {
+            source:
+                RenderSource::Many {
+                    rotate: Some(timing),
+                    ..
                 },
+            ..
+        }) = self.config.render_passes.get(index)
+        {
+            self.render_pass_timers.insert(
+                index,
+                RotateTimer::spawn(
+                    index,
+                    Duration::from_secs(*timing as u64),
+                    self.mq_send.clone(),
+                ),
             );
-            let scaled_image = self.render_pass_resizes.get(&index).unwrap();
+        }
+    }
+
+    /// Move a render pass to its next or previous image
+    ///
+    /// Returns false if the render pass does not have multiple sources.
+    pub fn rotate(&mut self, index: usize, backwards: bool) -> bool {
+        let Some(RenderPass {
+            source: RenderSource::Many { images, rand, .. },
+            target,
+            ..
+        }) = self.config.render_passes.get(index)
+        else {
+            return false;
+        };
+
+        let len = images.len();
+        let current = self
+            .render_pass_rotate_index
+            .get(&index)
+            .copied()
+            .unwrap_or(0);
+        let next = if backwards {
+            (current + len - 1) % len
+        } else {
+            self.render_pass_next
+                .remove(&index)
+                .filter(|next| *next < len)
+                .unwrap_or_else(|| next_index(current, len, *rand))
+        };
+
+        self.render_pass_rotate_index.insert(index, next);
+        self.render_pass_override.remove(&index);
 
-            if let RenderTarget::Display(s) = &pass.target {
-                let display = self.displays.get_mut(s).unwrap();
+        self.damage_target(target);
+        self.prefetch(index);
+        true
+    }
+
+    /// Decode the image a rotating render pass shows next in the background
+    fn prefetch(&mut self, index: usize) {
+        let Some(RenderPass {
+            source:
+                RenderSource::Many {
+                    images,
+                    rand,
+                    rotate: Some(_),
+                },
+            ..
+        }) = self.config.render_passes.get(index)
+        else {
+            return;
+        };
+
+        let current = self
+            .render_pass_rotate_index
+            .get(&index)
+            .copied()
+            .unwrap_or(0);
+        let next = match rand {
+            true => *self
+                .render_pass_next
+                .entry(index)
+                .or_insert_with(|| next_index(current, images.len(), true)),
+            false => next_index(current, images.len(), false),
+        };
+
+        let filter = self.config.render_passes[index].resize.filter;
+        let Some(image) = images.get(next).cloned() else {
+            return;
+        };
+        // Prefetched once the displays it is drawn to are known
+        let Some(scale) = self.image_scale(&image) else {
+            return;
+        };
+
+        if let Some(key) = self
+            .config
+            .images
+            .get(&image)
+            .and_then(LoadedImage::decode_key)
+        {
+            self.image_cache
+                .prefetch(&key, scale, filter.into(), &self.workers, &self.mq_send);
+        }
+    }
 
-                display.draw(qh, &scaled_image, total_region);
-            } else if let RenderTarget::Group(s) = &pass.target {
-                let group = self.config.groups.get(s).unwrap();
+    pub fn handle_message(&mut self, message: Message, qh: &QueueHandle<Self>) {
+        match message {
+            Message::Rotate(index) => {
+                if !self.render_pass_paused.contains(&index) && self.rotate(index, false) {
+                    self.draw(qh);
+                }
+            }
+            Message::Decoded(key, scale, image) => self.image_cache.finish(key, scale, image),
+            Message::Request(request, reply) => {
+                let _ = reply.send(self.handle_request(request, qh));
+            }
+            Message::Resized { resizes, decoded } => {
+                if let Some((key, scale, image)) = decoded {
+                    self.image_cache.finish(key, scale, Some(image));
+                }
 
-                for display in &group.displays {
-                    let Some(display) = self.displays.get_mut(display) else {
+                for (key, resized) in resizes {
+                    // Resizes of images changed by a reload are stale
+                    if !self.render_pass_pending.remove(&key) {
                         continue;
-                    };
-                    display.draw(qh, &scaled_image, total_region);
+                    }
+                    match resized {
+                        Some(resized) => {
+                            self.render_pass_resizes.insert(key, Arc::new(resized));
+                        }
+                        None => {
+                            self.render_pass_failed.insert(key);
+                        }
+                    }
+                }
+
+                self.draw(qh);
+            }
+            Message::Composited {
+                display,
+                serial,
+                buffer,
+            } => {
+                // Composites replaced or cancelled since are stale
+                if !self.composites.finish(&display, serial) {
+                    return;
+                }
+
+                let Some(output) = self.displays.get_mut(&display) else {
+                    return;
+                };
+                match buffer {
+                    Some((dim, pixels)) => output.draw(qh, dim, &pixels),
+                    None => {
+                        warn!("filling display '{display}' with black");
+                        output.draw_color(qh, self.single_pixel.as_ref(), [0, 0, 0, 255]);
+                    }
                 }
             }
         }
     }
 
-    pub fn group_region(&self, group: &DisplayGroup) -> Option<Region> {
-        group
+    /// Reload the config from disk, redrawing only what changed
+    ///
+    /// The running config is kept if the new config fails to load.
+    pub fn reload(&mut self, qh: &QueueHandle<Self>) -> Result<(), ConfigError> {
+        let config = match Config::load(&self.config.path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("failed to reload config, keeping previous config");
+                error!("{e}");
+                return Err(e);
+            }
+        };
+
+        info!("config reloaded");
+
+        let changed_images = config
+            .images
+            .iter()
+            .filter(|(ident, image)| {
+                self.config
+                    .images
+                    .get(*ident)
+                    .is_none_or(|old| old.differs(image))
+            })
+            .map(|(ident, _)| ident.clone())
+            .collect::<HashSet<_>>();
+
+        let changed_groups = config
+            .groups
+            .iter()
+            .filter(|(ident, group)| self.config.groups.get(*ident) != Some(group))
+            .map(|(ident, _)| ident.clone())
+            .collect::<HashSet<_>>();
+
+        let mut changed_displays = config
             .displays
             .iter()
-            .filter_map(|d| self.displays.get(d).map(|d| d.region))
-            // .map(f)
-            .fold(None, |r, n| Some(r.map_or(n, |r| r.combine(n))))
+            .filter(|(ident, display)| self.config.displays.get(*ident) != Some(display))
+            .map(|(ident, _)| ident.clone())
+            .collect::<HashSet<_>>();
+
+        // Outputs claimed by a more specific entry under the new config
+        for display in self.displays.values() {
+            let Some(info) = self.output_state.info(&display.output) else {
+                continue;
+            };
+            let matched = match_output(&config.displays, &info);
+
+            let moved = match matched.iter().position(|entry| **entry == display.entry) {
+                Some(position) => matched[..position]
+                    .iter()
+                    .any(|entry| !self.displays.contains_key(*entry)),
+                None => true,
+            };
+            if moved {
+                changed_displays.insert(display.entry.clone());
+            }
+        }
+
+        // Drop displays which are no longer configured or now match another output
+        self.displays.retain(|_, display| {
+            config.displays.contains_key(&display.entry)
+                && !changed_displays.contains(&display.entry)
+        });
+
+        let affected_passes = affected_passes(
+            &self.config,
+            &config,
+            &changed_images,
+            &changed_displays,
+            &changed_groups,
+        );
+
+        // Displays drawn by removed or changed passes lose their layer
+        for target in stale_targets(&self.config, &config, &affected_passes) {
+            self.damage_target(target);
+        }
+
+        self.config = config;
+
+        for output in self.output_state.outputs().collect::<Vec<_>>() {
+            self.add_display(&output, qh);
+        }
+
+        let pass_count = self.config.render_passes.len();
+        self.render_pass_resizes
+            .retain(|key, _| !changed_images.contains(&key.image));
+        self.render_pass_pending
+            .retain(|key| !changed_images.contains(&key.image));
+        self.render_pass_failed.clear();
+        self.composites.clear();
+        self.render_pass_rotate_index
+            .retain(|index, _| *index < pass_count && !affected_passes.contains(index));
+        self.render_pass_next
+            .retain(|index, _| *index < pass_count && !affected_passes.contains(index));
+        self.image_cache.set_budget(self.config.memory_budget);
+        self.disk_cache = DiskCache::new(self.config.disk_budget);
+        let dropped_overrides = retain_overrides(
+            &mut self.render_pass_override,
+            &self.config,
+            &affected_passes,
+        );
+        self.render_pass_paused.retain(|index| *index < pass_count);
+
+        for index in affected_passes.into_iter().chain(dropped_overrides) {
+            self.damage_target(&self.config.render_passes[index].target);
+        }
+
+        self.watcher
+            .watch(self.config.watch_paths(), self.config.watch_dirs());
+        self.start_rotations();
+        self.draw(qh);
+
+        Ok(())
     }
 
     pub fn add_display(&mut self, output: &WlOutput, qh: &QueueHandle<Self>) -> bool {
-        let info = &self.output_state.info(&output).unwrap();
+        let Some(info) = &self.output_state.info(output) else {
+            return false;
+        };
 
-        // Skip if display has no name
-        // TODO: Support other identification methods
-        if info.name.is_none() {
+        // Already drawing to this output
+        if self.displays.values().any(|d| d.output == *output) {
             return false;
         }
 
-        let name = info.name.as_ref().unwrap();
-        let Some(name) = self.display_ident_map.get(name) else {
-            warn!("skipping display: {name}");
+        let label = output_label(info);
+
+        // Entries draw to a single output, except fallbacks
+        let entry = match_output(&self.config.displays, info)
+            .into_iter()
+            .find(|entry| {
+                if self.config.displays[*entry].is_fallback() || !self.displays.contains_key(*entry)
+                {
+                    return true;
+                }
+                warn!("several outputs match display '{entry}', skipping it for {label}");
+                false
+            })
+            .cloned();
+        let Some(entry) = entry else {
+            warn!("skipping display: {label}");
+            return false;
+        };
+
+        let name = if self.config.displays[&entry].is_fallback() {
+            match &info.name {
+                Some(output_name) => format!("{entry}/{output_name}"),
+                None => format!("{entry}/{}", info.id),
+            }
+        } else {
+            entry.clone()
+        };
+
+        // Without xdg-output, derive the logical size from the current mode
+        let Some((width, height)) = info.logical_size.or_else(|| {
+            let mode = info.modes.iter().find(|m| m.current)?;
+            let (mut width, mut height) = mode.dimensions;
+            if swaps_axes(info.transform) {
+                std::mem::swap(&mut width, &mut height);
+            }
+            let scale = info.scale_factor.max(1);
+            Some((width / scale, height / scale))
+        }) else {
+            warn!("skipping display with unknown size: {name}");
             return false;
         };
+        let (x, y) = info.logical_position.unwrap_or(info.location);
 
         let surface = self.compositor_state.create_surface(qh);
 
         let layer = self.layer_shell.create_layer_surface(
-            &qh,
+            qh,
             surface,
             Layer::Bottom,
             Some(format!("wanipaper_layer_{}", info.id)),
-            Some(&output),
+            Some(output),
         );
         layer.set_keyboard_interactivity(KeyboardInteractivity::None);
         layer.set_anchor(Anchor::all());
 
-        let (width, height) = info.logical_size.unwrap();
-        let (x, y) = info.logical_position.unwrap();
-
         let min = Vector2::new(x, y);
         let max = Vector2::new(x + width, y + height);
+        let region = Region::new(min, max);
+
+        let (mut physical_width, mut physical_height) = info.physical_size;
+        if swaps_axes(info.transform) {
+            std::mem::swap(&mut physical_width, &mut physical_height);
+        }
+        let physical_size = Vector2::new(physical_width, physical_height);
+
+        let target = &self.config.displays[&entry];
+        let (bezel, dpi) = (target.bezel, target.dpi);
+        if bezel.unit == BezelUnit::Mm && dpi.is_none() && physical_size.x <= 0 {
+            warn!("display '{name}' has an unknown physical size, bezel ignored");
+        }
+
+        let surface_scale = self
+            .fractional_scale
+            .as_ref()
+            .map(|f| f.create(layer.wl_surface(), qh));
 
         layer.set_size(width as u32, height as u32);
         layer.commit();
@@ -187,38 +878,140 @@ impl State {
         self.displays.insert(
             name.clone(),
             Display {
+                entry,
+                output: output.clone(),
                 layer: (layer, 0),
                 pool,
                 first: true,
                 damaged: Arc::new(AtomicBool::new(true)),
-                region: Region::new(min, max),
+                region,
+                scale: info.scale_factor.max(1),
+                surface_scale,
+                transform: info.transform,
+                physical_size,
+                bezel,
+                dpi,
             },
         );
 
         info!("display added: '{}'", name);
+        self.damage_passes_with(&name);
 
-        return true;
+        true
     }
 
     pub fn remove_display(&mut self, output: &WlOutput) -> bool {
-        let info = &self.output_state.info(&output).unwrap();
-
-        // Skip if display has no name
-        // TODO: Support other identification methods
-        if info.name.is_none() {
-            return false;
-        }
-
-        let Some(name) = self.display_ident_map.get(info.name.as_ref().unwrap()) else {
+        let Some(name) = self
+            .displays
+            .iter()
+            .find(|(_, d)| d.output == *output)
+            .map(|(name, _)| name.clone())
+        else {
             return false;
         };
 
-        self.displays.remove(name);
+        self.damage_passes_with(&name);
+        self.displays.remove(&name);
+        self.composites.cancel(&name);
 
         info!("display removed: '{}'", name);
 
-        return true;
+        true
+    }
+}
+
+/// Name of an output for logging, falling back to its make and model
+fn output_label(info: &OutputInfo) -> String {
+    info.name
+        .clone()
+        .unwrap_or_else(|| format!("{} {}", info.make, info.model))
+}
+
+/// Render passes of the new config whose image, target or settings changed
+fn affected_passes(
+    old: &Config,
+    new: &Config,
+    changed_images: &HashSet<String>,
+    changed_displays: &HashSet<String>,
+    changed_groups: &HashSet<String>,
+) -> HashSet<usize> {
+    let mut affected_passes = HashSet::new();
+    for (index, pass) in new.render_passes.iter().enumerate() {
+        let pass_images: Vec<&String> = match &pass.source {
+            RenderSource::Single(image) => vec![image],
+            RenderSource::Many { images, .. } => images.iter().collect(),
+        };
+
+        let target_changed = match &pass.target {
+            RenderTarget::Display(d) => changed_displays.contains(d),
+            RenderTarget::Group(g) => {
+                changed_groups.contains(g)
+                    || match &new.groups[g].displays {
+                        GroupDisplays::All => !changed_displays.is_empty(),
+                        GroupDisplays::Listed(displays) => {
+                            displays.iter().any(|d| changed_displays.contains(d))
+                        }
+                    }
+            }
+        };
+
+        if old.render_passes.get(index) != Some(pass)
+            || pass_images.iter().any(|i| changed_images.contains(*i))
+            || target_changed
+        {
+            affected_passes.insert(index);
+        }
     }
+    affected_passes
+}
+
+/// Targets of the old config's render passes which were affected or removed
+///
+/// The displays they drew to must be redrawn, even if no pass of the new
+/// config draws there.
+fn stale_targets<'a>(
+    old: &'a Config,
+    new: &Config,
+    affected_passes: &HashSet<usize>,
+) -> Vec<&'a RenderTarget> {
+    old.render_passes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index >= new.render_passes.len() || affected_passes.contains(index))
+        .map(|(_, pass)| &pass.target)
+        .collect()
+}
+
+/// Drop overrides of removed or affected render passes, and of images removed
+/// from the config, returning the unaffected render passes which lost theirs
+fn retain_overrides(
+    overrides: &mut HashMap<usize, String>,
+    config: &Config,
+    affected_passes: &HashSet<usize>,
+) -> Vec<usize> {
+    let pass_count = config.render_passes.len();
+    let mut dropped = Vec::new();
+    overrides.retain(|index, image| {
+        if *index >= pass_count || affected_passes.contains(index) {
+            return false;
+        }
+        if !config.images.contains_key(image) {
+            dropped.push(*index);
+            return false;
+        }
+        true
+    });
+    dropped
+}
+
+/// Image after `current` in a rotation, a different one at random if `rand`
+fn next_index(current: usize, len: usize, rand: bool) -> usize {
+    let step = if rand && len > 1 {
+        random_range(1..len)
+    } else {
+        1
+    };
+    (current + step) % len
 }
 
 impl LayerShellHandler for State {
@@ -234,7 +1027,7 @@ impl LayerShellHandler for State {
         _configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        for (_id, disp) in &mut self.displays {
+        for disp in self.displays.values_mut() {
             if disp.layer.0 != *layer {
                 continue;
             }
@@ -251,19 +1044,39 @@ impl CompositorHandler for State {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
+        for disp in self.displays.values_mut() {
+            if disp.layer.0.wl_surface() != surface || disp.scale == new_factor {
+                continue;
+            }
+
+            disp.scale = new_factor.max(1);
+            disp.damaged.store(true, Ordering::Release);
+        }
+
+        self.draw(qh);
     }
 
     fn transform_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
+        for disp in self.displays.values_mut() {
+            if disp.layer.0.wl_surface() != surface || disp.transform == new_transform {
+                continue;
+            }
+
+            disp.transform = new_transform;
+            disp.damaged.store(true, Ordering::Release);
+        }
+
+        self.draw(qh);
     }
 
     fn frame(
@@ -317,15 +1130,17 @@ impl OutputHandler for State {
     ) {
         self.remove_display(&output);
         self.add_display(&output, qh);
+        self.draw(qh);
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
         self.remove_display(&output);
+        self.draw(qh);
     }
 }
 
@@ -398,3 +1213,142 @@ delegate_shm!(State);
 delegate_seat!(State);
 delegate_pointer!(State);
 delegate_registry!(State);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::load;
+
+    const DISPLAYS: &str = r##"
+        [displays.primary]
+        name = "DP-1"
+
+        [displays.secondary]
+        name = "DP-2"
+
+        [images.red]
+        color = "#ff0000"
+
+        [images.blue]
+        color = "#0000ff"
+    "##;
+
+    #[test]
+    fn reload_drops_overrides_of_removed_images() {
+        let config = load(&format!(
+            r#"{DISPLAYS}
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+
+            [[renderpass]]
+            source = "blue"
+            target = "secondary"
+            "#
+        ))
+        .unwrap();
+
+        // Pass 0 shows an image only pass 1 uses
+        let mut overrides = HashMap::from([(0, "blue".to_string())]);
+        let dropped = retain_overrides(&mut overrides, &config, &HashSet::new());
+        assert!(dropped.is_empty());
+        assert_eq!(overrides[&0], "blue");
+
+        // Removing the image from the config drops the override
+        let config = load(
+            r##"
+            [displays.primary]
+            name = "DP-1"
+
+            [images.red]
+            color = "#ff0000"
+
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+            "##,
+        )
+        .unwrap();
+        assert!(!config.images.contains_key("blue"));
+
+        let dropped = retain_overrides(&mut overrides, &config, &HashSet::new());
+        assert_eq!(dropped, [0]);
+        assert!(overrides.is_empty());
+    }
+
+    #[test]
+    fn reload_drops_overrides_of_changed_passes() {
+        let config = load(&format!(
+            r#"{DISPLAYS}
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+
+            [[renderpass]]
+            source = "blue"
+            target = "secondary"
+            "#
+        ))
+        .unwrap();
+
+        let mut overrides = HashMap::from([(0, "blue".to_string()), (1, "red".to_string())]);
+        let dropped = retain_overrides(&mut overrides, &config, &HashSet::from([1]));
+        assert!(dropped.is_empty());
+        assert_eq!(overrides, HashMap::from([(0, "blue".to_string())]));
+    }
+
+    #[test]
+    fn reload_redraws_targets_of_removed_and_retargeted_passes() {
+        let old = load(&format!(
+            r#"{DISPLAYS}
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+
+            [[renderpass]]
+            name = "logo"
+            source = "blue"
+            target = "primary"
+            "#
+        ))
+        .unwrap();
+        let none = HashSet::new();
+
+        // The logo layered on primary is removed, the pass below is unchanged
+        let removed = load(&format!(
+            r#"{DISPLAYS}
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+            "#
+        ))
+        .unwrap();
+        let affected = affected_passes(&old, &removed, &none, &none, &none);
+        assert!(affected.is_empty());
+        assert_eq!(
+            stale_targets(&old, &removed, &affected),
+            [&RenderTarget::Display("primary".to_string())]
+        );
+
+        // The logo moves to secondary, primary still loses it
+        let retargeted = load(&format!(
+            r#"{DISPLAYS}
+            [[renderpass]]
+            source = "red"
+            target = "primary"
+
+            [[renderpass]]
+            name = "logo"
+            source = "blue"
+            target = "secondary"
+            "#
+        ))
+        .unwrap();
+        let affected = affected_passes(&old, &retargeted, &none, &none, &none);
+        assert_eq!(affected, HashSet::from([1]));
+        assert_eq!(
+            stale_targets(&old, &retargeted, &affected),
+            [&RenderTarget::Display("primary".to_string())]
+        );
+    }
+}
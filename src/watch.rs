@@ -0,0 +1,106 @@
+use image::ImageFormat;
+use log::{error, warn};
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{Epoll, EpollEvent, EpollFlags},
+        inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Watches files for changes using inotify
+///
+/// Parent directories are watched rather than the files themselves, so
+/// editors which save by replacing the file are still picked up. Scanned
+/// directories report any image or subdirectory added or removed.
+pub struct Watcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    files: HashSet<PathBuf>,
+    scanned: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    pub fn new(epoll: &Epoll, queue_id: u64) -> Result<Watcher, Box<dyn Error>> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        epoll.add(&inotify, EpollEvent::new(EpollFlags::EPOLLIN, queue_id))?;
+
+        Ok(Watcher {
+            inotify,
+            dirs: HashMap::new(),
+            files: HashSet::new(),
+            scanned: HashSet::new(),
+        })
+    }
+
+    /// Replace the set of watched files and scanned directories
+    pub fn watch<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a Path>,
+        scanned: impl IntoIterator<Item = &'a Path>,
+    ) {
+        for (wd, _) in self.dirs.drain() {
+            let _ = self.inotify.rm_watch(wd);
+        }
+        self.files.clear();
+        self.scanned = scanned.into_iter().map(Path::to_path_buf).collect();
+
+        let mut dirs = self.scanned.clone();
+        for path in paths {
+            self.files.insert(path.to_path_buf());
+            if let Some(dir) = path.parent() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+
+        for dir in dirs {
+            let flags = AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE;
+
+            match self.inotify.add_watch(&dir, flags) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, dir);
+                }
+                Err(e) => warn!("failed to watch {dir:?}: {e}"),
+            }
+        }
+    }
+
+    /// Drain pending events, returning true if any watched file changed
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => return changed,
+                Err(e) => {
+                    error!("failed to read inotify events: {e}");
+                    return changed;
+                }
+            };
+
+            for event in events {
+                let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+
+                // Files are only complete once written, directories once created
+                let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+                let created = event.mask.contains(AddWatchFlags::IN_CREATE);
+                let scanned = self.scanned.contains(dir)
+                    && (is_dir || (!created && ImageFormat::from_path(&path).is_ok()));
+
+                changed |= scanned || (!created && self.files.contains(&path));
+            }
+        }
+    }
+}
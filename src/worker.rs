@@ -0,0 +1,52 @@
+//! Pool of threads for decoding, resizing and compositing images
+//!
+//! Jobs report back through the message queue, so the Wayland thread never
+//! waits on them.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct WorkerPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+    /// Start a thread per available core
+    pub fn new() -> WorkerPool {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..threads {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                // The lock is released before running the job
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // Jobs report their own failures, the worker outlives them
+                    Ok(job) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => return,
+                }
+            });
+        }
+
+        WorkerPool { jobs }
+    }
+
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        WorkerPool::new()
+    }
+}
@@ -0,0 +1,63 @@
+//! XDG base directory lookup
+//!
+//! <https://specifications.freedesktop.org/basedir-spec/latest/>
+
+use std::path::PathBuf;
+
+/// `$XDG_CONFIG_HOME`, defaulting to `~/.config`
+pub fn config_home() -> Option<PathBuf> {
+    env_dir("XDG_CONFIG_HOME").or_else(|| home_join(".config"))
+}
+
+/// `$XDG_CONFIG_DIRS`, defaulting to `/etc/xdg`
+pub fn config_dirs() -> Vec<PathBuf> {
+    env_dirs("XDG_CONFIG_DIRS").unwrap_or_else(|| vec![PathBuf::from("/etc/xdg")])
+}
+
+/// `$XDG_DATA_HOME`, defaulting to `~/.local/share`
+pub fn data_home() -> Option<PathBuf> {
+    env_dir("XDG_DATA_HOME").or_else(|| home_join(".local/share"))
+}
+
+/// `$XDG_DATA_DIRS`, defaulting to `/usr/local/share` and `/usr/share`
+pub fn data_dirs() -> Vec<PathBuf> {
+    env_dirs("XDG_DATA_DIRS").unwrap_or_else(|| {
+        vec![
+            PathBuf::from("/usr/local/share"),
+            PathBuf::from("/usr/share"),
+        ]
+    })
+}
+
+/// `$XDG_CACHE_HOME`, defaulting to `~/.cache`
+pub fn cache_home() -> Option<PathBuf> {
+    env_dir("XDG_CACHE_HOME").or_else(|| home_join(".cache"))
+}
+
+/// `$XDG_RUNTIME_DIR`, which has no default
+pub fn runtime_dir() -> Option<PathBuf> {
+    env_dir("XDG_RUNTIME_DIR")
+}
+
+fn home_join(path: &str) -> Option<PathBuf> {
+    std::env::home_dir()
+        .filter(|home| home.is_absolute())
+        .map(|home| home.join(path))
+}
+
+/// Single directory from the environment, relative paths are invalid
+fn env_dir(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Colon separated directories from the environment, relative paths are ignored
+fn env_dirs(var: &str) -> Option<Vec<PathBuf>> {
+    let dirs = std::env::var_os(var)?;
+    let dirs = std::env::split_paths(&dirs)
+        .filter(|path| path.is_absolute())
+        .collect::<Vec<_>>();
+
+    (!dirs.is_empty()).then_some(dirs)
+}